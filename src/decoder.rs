@@ -30,8 +30,8 @@ use smallvec::SmallVec;
 use crate::{
     vpx_error_string,
     vpx_sys::{
-        self, vpx_codec_ctx_t, vpx_codec_err_t::VPX_CODEC_OK, vpx_codec_frame_buffer_t, vpx_codec_iter_t, vpx_color_range, vpx_color_space,
-        vpx_image_t, vpx_img_fmt, VPX_DECODER_ABI_VERSION,
+        self, vp8_dec_control_id, vpx_codec_ctx_t, vpx_codec_err_t::VPX_CODEC_OK, vpx_codec_frame_buffer_t, vpx_codec_iter_t, vpx_color_range,
+        vpx_color_space, vpx_image_t, vpx_img_fmt, VPX_DECODER_ABI_VERSION, VPX_SS_MAX_LAYERS,
     },
 };
 
@@ -165,17 +165,30 @@ pub struct VpxDecoder {
     iter: vpx_codec_iter_t,
     buffer_pool_ptr: *const BufferPool,
     frame_pool_initialized: AtomicBool,
+    frame_metadata: Option<Variant>,
 }
 
 unsafe impl Send for VpxDecoder {}
 unsafe impl Sync for VpxDecoder {}
 
 impl Codec<VideoDecoder> for VpxDecoder {
-    fn configure(&mut self, _params: Option<&CodecParameters>, _options: Option<&Variant>) -> Result<()> {
+    fn configure(&mut self, _params: Option<&CodecParameters>, options: Option<&Variant>) -> Result<()> {
+        if let Some(options) = options.and_then(|options| options.dict_iter()) {
+            for (key, value) in options {
+                self.set_option(key, value)?;
+            }
+        }
+
         Ok(())
     }
 
-    fn set_option(&mut self, _name: &str, _value: &Variant) -> Result<()> {
+    fn set_option(&mut self, name: &str, value: &Variant) -> Result<()> {
+        if name == "spatial_layer" {
+            if let Some(spatial_layer) = value.get_uint32() {
+                self.set_spatial_layer(spatial_layer)?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -191,6 +204,8 @@ impl Decoder<VideoDecoder> for VpxDecoder {
             return Err(Error::Invalid(vpx_error_string(ret)));
         }
 
+        self.frame_metadata = Some(self.get_frame_metadata());
+
         Ok(())
     }
 
@@ -199,43 +214,18 @@ impl Decoder<VideoDecoder> for VpxDecoder {
         _config: &VideoDecoder,
         pool: Option<&Arc<FramePool<VideoFrame<'static>>>>,
     ) -> Result<SharedFrame<VideoFrame<'static>>> {
-        let img = &self.get_image()?;
-
-        let pool = if let Some(pool) = pool {
-            pool
-        } else {
-            if !img.has_frame_buffer() {
-                return img.convert_to_frame().map(SharedFrame::<VideoFrame<'static>>::new);
-            }
-
-            let (buffer, buffer_planes, desc) = img.convert_to_buffer()?;
-            let frame = VideoFrame::from_shared_buffer_with_descriptor(desc, buffer, &buffer_planes)?;
-            return Ok(SharedFrame::<VideoFrame<'static>>::new(frame));
-        };
-
-        if !img.has_frame_buffer() {
-            let desc = img.descriptor()?;
-
-            self.init_pool(&desc, pool, None);
-
-            let frame = img.convert_to_frame()?;
-            let mut pooled_frame = pool.get_frame_with_descriptor(desc)?;
-            frame.convert_to(pooled_frame.write().unwrap())?;
-
-            Ok(pooled_frame)
-        } else {
-            let (buffer, buffer_planes, desc) = img.convert_to_buffer()?;
-
-            self.init_pool(&desc, pool, Some(Box::new(EmptyFrameCreator)));
-
-            let mut pooled_frame = pool.get_frame_with_descriptor(desc.clone())?;
-            pooled_frame.write().unwrap().attach_shared_buffer_with_descriptor(desc, buffer, &buffer_planes)?;
+        let mut frame = self.get_frame(pool)?;
 
-            Ok(pooled_frame)
+        if let (Some(metadata), Some(frame)) = (&self.frame_metadata, frame.write()) {
+            frame.metadata = Some(metadata.clone());
         }
+
+        Ok(frame)
     }
 
     fn flush(&mut self, _config: &VideoDecoder) -> Result<()> {
+        self.frame_metadata = None;
+
         let ret = unsafe { vpx_sys::vpx_codec_decode(&mut self.ctx, ptr::null(), 0, ptr::null_mut(), 0) };
 
         self.iter = ptr::null_mut();
@@ -276,7 +266,7 @@ unsafe extern "C" fn release_frame_buffer(_priv_: *mut c_void, fb: *mut vpx_code
 }
 
 impl VpxDecoder {
-    pub fn new(id: CodecID, _params: &VideoDecoderParameters, _options: Option<&Variant>) -> Result<Self> {
+    pub fn new(id: CodecID, _params: &VideoDecoderParameters, options: Option<&Variant>) -> Result<Self> {
         let (iface, name) = match id {
             CodecID::VP8 => (unsafe { vpx_sys::vpx_codec_vp8_dx() }, VP8_CODEC_NAME),
             CodecID::VP9 => (unsafe { vpx_sys::vpx_codec_vp9_dx() }, VP9_CODEC_NAME),
@@ -306,14 +296,39 @@ impl VpxDecoder {
             }
         }
 
-        Ok(Self {
+        let mut decoder = Self {
             id,
             name,
             ctx: unsafe { ctx.assume_init() },
             iter: ptr::null_mut(),
             buffer_pool_ptr: pool_ptr,
             frame_pool_initialized: AtomicBool::new(false),
-        })
+            frame_metadata: None,
+        };
+
+        decoder.configure(None, options)?;
+
+        Ok(decoder)
+    }
+
+    // Decode the spatial layers of VP9 SVC superframes up to the given layer,
+    // takes effect from the next packet
+    pub fn set_spatial_layer(&mut self, spatial_layer: u32) -> Result<()> {
+        if self.id != CodecID::VP9 {
+            return Err(unsupported_error!("spatial_layer"));
+        }
+
+        if spatial_layer >= VPX_SS_MAX_LAYERS {
+            return Err(Error::Invalid(format!("spatial layer {} exceeds {}", spatial_layer, VPX_SS_MAX_LAYERS - 1).into()));
+        }
+
+        let ret =
+            unsafe { vpx_sys::vpx_codec_control_(&mut self.ctx, vp8_dec_control_id::VP9_DECODE_SVC_SPATIAL_LAYER as c_int, spatial_layer as c_int) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
     }
 
     fn init_pool(
@@ -328,6 +343,75 @@ impl VpxDecoder {
         }
     }
 
+    fn get_frame(&mut self, pool: Option<&Arc<FramePool<VideoFrame<'static>>>>) -> Result<SharedFrame<VideoFrame<'static>>> {
+        let img = &self.get_image()?;
+
+        let pool = if let Some(pool) = pool {
+            pool
+        } else {
+            if !img.has_frame_buffer() {
+                return img.convert_to_frame().map(SharedFrame::<VideoFrame<'static>>::new);
+            }
+
+            let (buffer, buffer_planes, desc) = img.convert_to_buffer()?;
+            let frame = VideoFrame::from_shared_buffer_with_descriptor(desc, buffer, &buffer_planes)?;
+            return Ok(SharedFrame::<VideoFrame<'static>>::new(frame));
+        };
+
+        if !img.has_frame_buffer() {
+            let desc = img.descriptor()?;
+
+            self.init_pool(&desc, pool, None);
+
+            let frame = img.convert_to_frame()?;
+            let mut pooled_frame = pool.get_frame_with_descriptor(desc)?;
+            frame.convert_to(pooled_frame.write().unwrap())?;
+
+            Ok(pooled_frame)
+        } else {
+            let (buffer, buffer_planes, desc) = img.convert_to_buffer()?;
+
+            self.init_pool(&desc, pool, Some(Box::new(EmptyFrameCreator)));
+
+            let mut pooled_frame = pool.get_frame_with_descriptor(desc.clone())?;
+            pooled_frame.write().unwrap().attach_shared_buffer_with_descriptor(desc, buffer, &buffer_planes)?;
+
+            Ok(pooled_frame)
+        }
+    }
+
+    // Quantizer index of the bitstream, 0 to 127 for VP8 and 0 to 255 for VP9,
+    // and reference buffer usage of the last decoded frame, VP8 reports LAST,
+    // GOLDEN and ALTREF as bits 0 to 2 while VP9 reports one bit per reference
+    // buffer slot and no references used
+    fn get_frame_metadata(&mut self) -> Variant {
+        let mut metadata = Variant::new_dict();
+        let mut value: c_int = 0;
+
+        if self.control(vp8_dec_control_id::VPXD_GET_LAST_QUANTIZER, &mut value).is_ok() {
+            metadata.dict_set("quantizer", value.into());
+        }
+
+        if self.control(vp8_dec_control_id::VP8D_GET_LAST_REF_UPDATES, &mut value).is_ok() {
+            metadata.dict_set("ref_updates", (value as u32).into());
+        }
+
+        if self.id == CodecID::VP8 && self.control(vp8_dec_control_id::VP8D_GET_LAST_REF_USED, &mut value).is_ok() {
+            metadata.dict_set("ref_used", (value as u32).into());
+        }
+
+        metadata
+    }
+
+    fn control(&mut self, id: vp8_dec_control_id, value: *mut c_int) -> Result<()> {
+        let ret = unsafe { vpx_sys::vpx_codec_control_(&mut self.ctx, id as c_int, value) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
+    }
+
     fn get_image(&mut self) -> Result<VpxImage> {
         let img = unsafe { vpx_sys::vpx_codec_get_frame(&mut self.ctx as *const _ as *mut _, &mut self.iter) };
         if img.is_null() {
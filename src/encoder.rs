@@ -0,0 +1,1656 @@
+use std::{
+    collections::VecDeque,
+    mem::{self, MaybeUninit},
+    os::raw::{c_int, c_uint, c_ulong},
+    ptr, slice,
+    sync::Arc,
+};
+
+use ctor::ctor;
+use media_codec::{
+    codec::{Codec, CodecBuilder, CodecID},
+    encoder::{register_encoder, Encoder, EncoderBuilder, VideoEncoder, VideoEncoderParameters},
+    packet::{Packet, PacketFlags},
+    CodecInformation, CodecParameters,
+};
+use media_core::{
+    buffer::BufferPool,
+    error::Error,
+    frame::{MappedPlanes, SharedFrame},
+    invalid_param_error, none_param_error,
+    rational::Rational64,
+    unsupported_error,
+    variant::Variant,
+    video::{PixelFormat, VideoFrame, VideoFrameDescriptor},
+    Result,
+};
+
+use crate::{
+    vpx_error_string,
+    vpx_sys::{
+        self, vp8e_enc_control_id, vp8e_tuning, vp9e_tune_content, vpx_active_map_t, vpx_bit_depth, vpx_codec_ctx_t, vpx_codec_cx_pkt_kind,
+        vpx_codec_enc_cfg_t, vpx_codec_err_t::VPX_CODEC_OK, vpx_codec_flags_t, vpx_codec_iter_t, vpx_enc_deadline_t, vpx_enc_frame_flags_t,
+        vpx_enc_pass, vpx_fixed_buf, vpx_image_t, vpx_img_fmt, vpx_roi_map_t, vpx_scaling_mode, vpx_svc_frame_drop, VPX_CODEC_USE_HIGHBITDEPTH,
+        VPX_CODEC_USE_OUTPUT_PARTITION, VPX_CODEC_USE_PSNR, VPX_DL_GOOD_QUALITY, VPX_DL_REALTIME, VPX_EFLAG_FORCE_KF, VPX_ENCODER_ABI_VERSION,
+        VPX_FRAME_IS_DROPPABLE, VPX_FRAME_IS_FRAGMENT, VPX_FRAME_IS_INVISIBLE, VPX_FRAME_IS_KEY, VPX_MAX_LAYERS, VPX_SS_MAX_LAYERS,
+    },
+};
+
+mod active_map;
+mod color;
+mod first_pass;
+mod frame_drop;
+mod level;
+mod ltr;
+mod quality;
+mod rate_control;
+mod resize;
+mod roi;
+mod segment;
+mod simulcast;
+mod speed;
+mod stats;
+mod svc;
+mod threading;
+mod timestamp;
+mod tpl;
+mod tuning;
+
+use active_map::ACTIVE_MAP_BLOCK_SIZE;
+pub use active_map::{ActiveMap, Region};
+pub use color::ColorSignalling;
+use color::VpxColorConfig;
+pub use first_pass::{FirstPass, FirstPassStats};
+pub use frame_drop::{FrameDropOptions, LayerDropMode};
+use ltr::LongTermReferences;
+pub use quality::{AqMode, ArnrType, QualityOptions};
+use rate_control::RateControllerBox;
+pub use rate_control::{
+    FrameDecision, FrameInfo, FrameResult, FrameUpdateType, GopDecision, GopFrame, KeyFrameDecision, RateControlConfig, RateControlMode,
+    RateControlType, RateController,
+};
+pub use resize::{ResizeOptions, ScalingMode};
+pub use roi::{ReferenceFrame, RoiMap, RoiSegment};
+use segment::SegmentKeyFrames;
+pub use simulcast::{SimulcastStream, VpxSimulcastEncoder};
+pub use speed::Speed;
+pub use stats::{EncodeStats, Psnr};
+use svc::LayerStructure;
+pub use svc::{ScalabilityMode, SpatialLayerInfo};
+pub use threading::{Auto, ThreadingOptions};
+use timestamp::Timestamps;
+pub use tpl::{TplBlockStats, TplFrameStats, TplGopStats};
+use tpl::{TplStatsCollector, TplStatsQueue};
+pub use tuning::{ContentType, Preset, Tuning, TuningOptions};
+
+fn pixel_format_to_vpx_img_fmt(format: PixelFormat) -> Option<(vpx_img_fmt, u32)> {
+    use vpx_img_fmt::*;
+
+    match format {
+        PixelFormat::YV12 => Some((VPX_IMG_FMT_YV12, 8)),
+        PixelFormat::I420 => Some((VPX_IMG_FMT_I420, 8)),
+        PixelFormat::I422 => Some((VPX_IMG_FMT_I422, 8)),
+        PixelFormat::I444 => Some((VPX_IMG_FMT_I444, 8)),
+        PixelFormat::NV12 => Some((VPX_IMG_FMT_NV12, 8)),
+        PixelFormat::I010 => Some((VPX_IMG_FMT_I42016, 10)),
+        PixelFormat::I012 => Some((VPX_IMG_FMT_I42016, 12)),
+        PixelFormat::I210 => Some((VPX_IMG_FMT_I42216, 10)),
+        PixelFormat::I212 => Some((VPX_IMG_FMT_I42216, 12)),
+        PixelFormat::I410 => Some((VPX_IMG_FMT_I44416, 10)),
+        PixelFormat::I412 => Some((VPX_IMG_FMT_I44416, 12)),
+        _ => None,
+    }
+}
+
+fn vp9_profile(fmt: vpx_img_fmt) -> c_uint {
+    use vpx_img_fmt::*;
+
+    match fmt {
+        VPX_IMG_FMT_I422 | VPX_IMG_FMT_I444 => 1,
+        VPX_IMG_FMT_I42016 => 2,
+        VPX_IMG_FMT_I42216 | VPX_IMG_FMT_I44416 => 3,
+        _ => 0,
+    }
+}
+
+fn vpx_bit_depth(depth: u32) -> vpx_bit_depth {
+    match depth {
+        10 => vpx_bit_depth::VPX_BITS_10,
+        12 => vpx_bit_depth::VPX_BITS_12,
+        _ => vpx_bit_depth::VPX_BITS_8,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PacketInfo {
+    pub temporal_layer_id: Option<u8>,
+    // The frame references only base layer frames, so forwarding can switch up
+    // to its layer here, the Y bit of RFC 7741
+    pub layer_sync: bool,
+    // Spatial layers carried by the superframe, in decoding order
+    pub spatial_layers: Vec<SpatialLayerInfo>,
+    // Set when the encoder was created with the stats or external_rate_control
+    // option
+    pub stats: Option<EncodeStats>,
+    // Size of the coded frame, smaller than the input when the encoder scales
+    // internally
+    pub coded_size: Option<(u32, u32)>,
+    // Stream of a simulcast encoder, 0 being the full resolution
+    pub stream_index: Option<u8>,
+    // The frame refreshed a long-term reference, acknowledge its pts once the
+    // receiver got it
+    pub long_term_reference: bool,
+    // The rate control dropped the frame, the packet is empty and only carries
+    // the timestamps of the input frame, requires the report_dropped_frames
+    // option
+    pub dropped: bool,
+    // No later frame references the frame, so forwarding can drop it
+    pub discardable: bool,
+    // The frame updates the references without being shown, VP8 alt-ref frames.
+    // libvpx stamps them one tick after the previous frame, which is the pts
+    // and dts of the next frame when the frames are one tick apart, so muxers
+    // requiring strictly increasing dts must merge them with the next packet
+    pub invisible: bool,
+    // The frame continues in the next packet, VP8 with the output_partitions
+    // option only
+    pub fragment: bool,
+    pub partition_id: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct VpxEncoderOptions {
+    scalability_mode: ScalabilityMode,
+    preset: Option<Preset>,
+    tuning: TuningOptions,
+    speed: Option<Speed>,
+    cpu_used: Option<c_int>,
+    deadline: Option<vpx_enc_deadline_t>,
+    threading: ThreadingOptions,
+    quality: QualityOptions,
+    frame_drop: FrameDropOptions,
+    resize: ResizeOptions,
+    // Stats of a first pass, enables the last pass of two-pass encoding
+    two_pass_stats: Option<Vec<u8>>,
+    first_pass: bool,
+    tpl: bool,
+    long_term_reference: bool,
+    // In seconds
+    segment_duration: Option<f64>,
+    scene_cut_key_frames: bool,
+    time_base: Option<Rational64>,
+    lossless: Option<bool>,
+    external_rate_control: bool,
+    stats: bool,
+    report_dropped_frames: bool,
+    output_partitions: bool,
+    target_level: Option<u32>,
+    level_stats: bool,
+    color: ColorSignalling,
+}
+
+impl VpxEncoderOptions {
+    fn update(&mut self, key: &str, value: &Variant) -> Result<()> {
+        match key {
+            "scalability_mode" => {
+                if let Some(mode) = value.get_string() {
+                    self.scalability_mode = mode.parse()?;
+                }
+            }
+            "preset" => {
+                if let Some(preset) = value.get_string() {
+                    self.preset = Some(preset.parse()?);
+                }
+            }
+            "speed" => {
+                if let Some(speed) = value.get_string() {
+                    self.speed = Some(speed.parse()?);
+                }
+            }
+            "cpu_used" => self.cpu_used = value.get_int32().or(self.cpu_used),
+            "deadline" => self.deadline = speed::parse_deadline(value)?.or(self.deadline),
+            "lossless" => self.lossless = value.get_bool().or(self.lossless),
+            "long_term_reference" => self.long_term_reference = value.get_bool().unwrap_or(self.long_term_reference),
+            // "num/den"
+            "time_base" => {
+                if let Some(time_base) = value.get_string() {
+                    let parsed = time_base.parse::<Rational64>().ok().filter(|time_base| *time_base.numer() > 0);
+                    self.time_base = Some(parsed.ok_or_else(|| invalid_param_error!(time_base))?);
+                }
+            }
+            "segment_duration" => self.segment_duration = value.get_double().or(self.segment_duration),
+            "scene_cut_key_frames" => self.scene_cut_key_frames = value.get_bool().unwrap_or(self.scene_cut_key_frames),
+            "first_pass" => self.first_pass = value.get_bool().unwrap_or(self.first_pass),
+            "tpl" => self.tpl = value.get_bool().unwrap_or(self.tpl),
+            "two_pass_stats" => self.two_pass_stats = value.get_buffer().or(self.two_pass_stats.take()),
+            "external_rate_control" => self.external_rate_control = value.get_bool().unwrap_or(self.external_rate_control),
+            "stats" => self.stats = value.get_bool().unwrap_or(self.stats),
+            "report_dropped_frames" => self.report_dropped_frames = value.get_bool().unwrap_or(self.report_dropped_frames),
+            "output_partitions" => self.output_partitions = value.get_bool().unwrap_or(self.output_partitions),
+            "target_level" => self.target_level = level::parse_level(value)?.or(self.target_level),
+            "level_stats" => self.level_stats = value.get_bool().unwrap_or(self.level_stats),
+            _ => {
+                if !self.tuning.update(key, value)? &&
+                    !self.threading.update(key, value)? &&
+                    !self.quality.update(key, value)? &&
+                    !self.frame_drop.update(key, value)? &&
+                    !self.resize.update(key, value)?
+                {
+                    self.color.update(key, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Individual tuning options override the preset
+    fn tuning(&self) -> TuningOptions {
+        let mut tuning = self.preset.map(|preset| preset.options()).unwrap_or_default();
+        tuning.merge(&self.tuning);
+        tuning
+    }
+}
+
+pub struct VpxEncoder {
+    id: CodecID,
+    name: &'static str,
+    ctx: vpx_codec_ctx_t,
+    cfg: vpx_codec_enc_cfg_t,
+    img_fmt: vpx_img_fmt,
+    bit_depth: u32,
+    deadline: vpx_enc_deadline_t,
+    frame_count: i64,
+    timestamps: Timestamps,
+    layers: Option<LayerStructure>,
+    ltr: Option<LongTermReferences>,
+    // Checked again when the bitrate changes
+    target_level: Option<u32>,
+    segments: Option<SegmentKeyFrames>,
+    packets: VecDeque<(Packet<'static>, PacketInfo)>,
+    packet_info: Option<PacketInfo>,
+    // Timestamps and info of the frames sent but not output yet
+    pending_frames: VecDeque<(i64, i64, PacketInfo)>,
+    frame_drop: FrameDropOptions,
+    // Coded size of the last VP8 keyframe
+    coded_size: Option<(u32, u32)>,
+    active_map: Option<ActiveMap>,
+    external_rate_control: bool,
+    stats: bool,
+    report_dropped_frames: bool,
+    color: ColorSignalling,
+    color_config: Option<VpxColorConfig>,
+    // Referenced by the config and the encoder, dropped after the context
+    two_pass_stats: Option<Vec<u8>>,
+    rate_controller: Option<RateControllerBox>,
+    tpl_stats: Option<TplStatsQueue>,
+    first_pass_data: Vec<u8>,
+    first_pass_stats: Vec<FirstPassStats>,
+}
+
+unsafe impl Send for VpxEncoder {}
+unsafe impl Sync for VpxEncoder {}
+
+impl Codec<VideoEncoder> for VpxEncoder {
+    fn configure(&mut self, params: Option<&CodecParameters>, options: Option<&Variant>) -> Result<()> {
+        if let Some(params) = params {
+            let params: VideoEncoderParameters = params.try_into()?;
+            if let Some(bit_rate) = params.encoder.bit_rate {
+                self.set_bit_rate(bit_rate)?;
+            }
+        }
+
+        if let Some(options) = options.and_then(|options| options.dict_iter()) {
+            // Apply the preset first so that individual tuning options override it
+            let (presets, options): (Vec<_>, Vec<_>) = options.partition(|(key, _)| key.as_str() == "preset");
+
+            for (key, value) in presets.into_iter().chain(options) {
+                self.set_option(key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_option(&mut self, key: &str, value: &Variant) -> Result<()> {
+        match key {
+            "bit_rate" => {
+                if let Some(bit_rate) = value.get_uint64() {
+                    self.set_bit_rate(bit_rate)?;
+                }
+            }
+            "preset" => {
+                if let Some(preset) = value.get_string() {
+                    self.set_tuning(&preset.parse::<Preset>()?.options())?;
+                }
+            }
+            "speed" => {
+                if let Some(speed) = value.get_string() {
+                    self.set_speed(speed.parse()?)?;
+                }
+            }
+            "cpu_used" => {
+                if let Some(cpu_used) = value.get_int32() {
+                    self.set_cpu_used(cpu_used)?;
+                }
+            }
+            "deadline" => {
+                if let Some(deadline) = speed::parse_deadline(value)? {
+                    self.deadline = deadline;
+                }
+            }
+            "lossless" => {
+                if let Some(lossless) = value.get_bool() {
+                    self.set_lossless(lossless)?;
+                }
+            }
+            "qp" => {
+                if let Some(qp) = value.get_uint32() {
+                    self.set_frame_qp(qp)?;
+                }
+            }
+            _ => {
+                let mut tuning = TuningOptions::default();
+                let mut threading = ThreadingOptions::default();
+                let mut quality = QualityOptions::default();
+                let mut frame_drop = FrameDropOptions::default();
+                let mut resize = ResizeOptions::default();
+
+                if tuning.update(key, value)? {
+                    self.set_tuning(&tuning)?;
+                } else if threading.update(key, value)? {
+                    self.set_threading(&threading)?;
+                } else if quality.update(key, value)? {
+                    self.set_quality(&quality)?;
+                } else if frame_drop.update(key, value)? {
+                    self.set_frame_drop(&frame_drop)?;
+                } else if resize.update(key, value)? {
+                    self.set_resize(&resize)?;
+                } else {
+                    self.color.update(key, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Encoder<VideoEncoder> for VpxEncoder {
+    fn send_frame(&mut self, _config: &VideoEncoder, pool: Option<&Arc<BufferPool>>, frame: SharedFrame<VideoFrame<'static>>) -> Result<()> {
+        let frame = frame.read();
+        let guard = frame.map()?;
+        let planes = guard.planes().ok_or_else(|| Error::Invalid("frame data is not mappable".into()))?;
+        let desc = frame.descriptor();
+
+        let (img_fmt, _) = pixel_format_to_vpx_img_fmt(desc.format).ok_or_else(|| unsupported_error!(desc.format))?;
+        if img_fmt != self.img_fmt {
+            return Err(unsupported_error!(desc.format));
+        }
+
+        if self.id == CodecID::VP9 {
+            self.set_color_config(self.color.resolve(desc, self.cfg.g_profile))?;
+        }
+
+        // The qp key is set by the application on the 0 to 63 scale of the
+        // encoder, unlike the bitstream quantizer index of the decoder
+        // metadata, and only taken when the application drives the rate control
+        if self.external_rate_control {
+            if let Some(qp) = frame.metadata.as_ref().and_then(|metadata| metadata.dict_get("qp")).and_then(|qp| qp.get_uint32()) {
+                self.set_frame_qp(qp)?;
+            }
+        }
+
+        let img = wrap_image(desc, &planes, img_fmt, self.bit_depth)?;
+
+        let (pts, duration) = self.timestamps.next(frame.pts, frame.duration, frame.time_base)?;
+        let first_frame = self.frame_count == 0;
+        self.frame_count += 1;
+
+        let mut flags = 0;
+        let mut info = PacketInfo::default();
+
+        if self.segments.as_mut().is_some_and(|segments| segments.is_boundary(pts)) {
+            flags |= VPX_EFLAG_FORCE_KF as vpx_enc_frame_flags_t;
+        }
+
+        let key_frame = first_frame || flags & VPX_EFLAG_FORCE_KF as vpx_enc_frame_flags_t != 0;
+
+        // Keyframes restart the pattern at the base layer
+        if let Some(layers) = self.layers.as_mut().filter(|_| key_frame) {
+            layers.restart();
+        }
+
+        if let Some(layers) = &self.layers {
+            info.temporal_layer_id = Some(layers.layer_id());
+            info.layer_sync = layers.is_layer_sync();
+
+            if self.id == CodecID::VP8 {
+                flags |= layers.flags() as vpx_enc_frame_flags_t;
+                self.control(vp8e_enc_control_id::VP8E_SET_TEMPORAL_LAYER_ID, layers.layer_id() as c_int)?;
+            } else {
+                let mut layer_id = layers.svc_layer_id();
+                let mut ref_frame_config = layers.ref_frame_config(key_frame);
+
+                self.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_LAYER_ID, &mut layer_id)?;
+                self.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_REF_FRAME_CONFIG, &mut ref_frame_config)?;
+            }
+        } else if let Some(ltr) = &mut self.ltr {
+            let decision = ltr.decide(pts);
+            info.long_term_reference = decision.updates_long_term_reference();
+
+            if self.id == CodecID::VP8 || decision.key_frame {
+                flags |= decision.vp8_flags() as vpx_enc_frame_flags_t;
+            }
+
+            if self.id == CodecID::VP9 {
+                let mut ref_frame_config = decision.vp9_ref_frame_config();
+                self.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_REF_FRAME_CONFIG, &mut ref_frame_config)?;
+            }
+        }
+
+        // The first pass outputs stats only, so no frame is dropped
+        let first_pass = self.cfg.g_pass == vpx_enc_pass::VPX_RC_FIRST_PASS;
+        if !first_pass {
+            self.pending_frames.push_back((pts, duration, info));
+        }
+
+        let ret = unsafe { vpx_sys::vpx_codec_encode(&mut self.ctx, &img, pts, duration as c_ulong, flags, self.deadline) };
+        if ret != VPX_CODEC_OK {
+            if !first_pass {
+                self.pending_frames.pop_back();
+            }
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        let key_frame = self.get_packets(pool)?;
+
+        if let Some(layers) = &mut self.layers {
+            layers.advance(key_frame);
+        }
+
+        if let Some(ltr) = self.ltr.as_mut().filter(|_| key_frame) {
+            ltr.key_frame(pts);
+        }
+
+        Ok(())
+    }
+
+    fn receive_packet(&mut self, _config: &VideoEncoder, _pool: Option<&Arc<BufferPool>>) -> Result<Packet<'static>> {
+        let (packet, info) = self.packets.pop_front().ok_or_else(|| Error::Again("no packet available".into()))?;
+
+        self.packet_info = Some(info);
+
+        Ok(packet)
+    }
+
+    fn flush(&mut self, _config: &VideoEncoder) -> Result<()> {
+        // The lookahead holds at most g_lag_in_frames frames, so the encoder is
+        // drained once as many flush calls in a row output nothing
+        let mut idle_calls = 0;
+
+        loop {
+            let ret = unsafe { vpx_sys::vpx_codec_encode(&mut self.ctx, ptr::null(), -1, 1, 0, self.deadline) };
+            if ret != VPX_CODEC_OK {
+                return Err(Error::Invalid(vpx_error_string(ret)));
+            }
+
+            let progress = (self.packets.len(), self.pending_frames.len(), self.first_pass_data.len());
+            self.get_packets(None)?;
+
+            if (self.packets.len(), self.pending_frames.len(), self.first_pass_data.len()) != progress {
+                idle_calls = 0;
+                continue;
+            }
+
+            idle_calls += 1;
+            if self.pending_frames.is_empty() || idle_calls > self.cfg.g_lag_in_frames {
+                break;
+            }
+        }
+
+        // Frames the drained encoder never output were dropped
+        while let Some((pts, duration, info)) = self.pending_frames.pop_front() {
+            self.push_dropped(None, pts, duration, info);
+        }
+
+        Ok(())
+    }
+}
+
+impl VpxEncoder {
+    pub fn new(id: CodecID, params: &VideoEncoderParameters, options: Option<&Variant>) -> Result<Self> {
+        let (iface, name) = match id {
+            CodecID::VP8 => (unsafe { vpx_sys::vpx_codec_vp8_cx() }, VP8_CODEC_NAME),
+            CodecID::VP9 => (unsafe { vpx_sys::vpx_codec_vp9_cx() }, VP9_CODEC_NAME),
+            _ => return Err(unsupported_error!(id)),
+        };
+
+        let mut opts = VpxEncoderOptions::default();
+        if let Some(options) = options.and_then(|options| options.dict_iter()) {
+            for (key, value) in options {
+                opts.update(key, value)?;
+            }
+        }
+
+        let pixel_format = params.video.format.unwrap_or(PixelFormat::I420);
+        let (img_fmt, bit_depth) = pixel_format_to_vpx_img_fmt(pixel_format).ok_or_else(|| unsupported_error!(pixel_format))?;
+        if id == CodecID::VP8 && !matches!(img_fmt, vpx_img_fmt::VPX_IMG_FMT_I420 | vpx_img_fmt::VPX_IMG_FMT_YV12 | vpx_img_fmt::VPX_IMG_FMT_NV12) {
+            return Err(unsupported_error!(pixel_format));
+        }
+
+        let width = params.video.width.ok_or_else(|| none_param_error!(params.video.width))?;
+        let height = params.video.height.ok_or_else(|| none_param_error!(params.video.height))?;
+
+        let mut cfg = MaybeUninit::zeroed();
+        let ret = unsafe { vpx_sys::vpx_codec_enc_config_default(iface, cfg.as_mut_ptr(), 0) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        let mut cfg: vpx_codec_enc_cfg_t = unsafe { cfg.assume_init() };
+        cfg.g_w = width.get();
+        cfg.g_h = height.get();
+
+        cfg.g_timebase = timestamp::encoder_time_base(opts.time_base)?;
+
+        if let Some(bit_rate) = params.encoder.bit_rate {
+            cfg.rc_target_bitrate = (bit_rate / 1000) as c_uint;
+        }
+
+        if let Some(threshold) = opts.frame_drop.threshold {
+            cfg.rc_dropframe_thresh = threshold;
+        }
+
+        opts.resize.apply(&mut cfg);
+
+        let segments = opts.segment_duration.map(|duration| SegmentKeyFrames::new(duration, cfg.g_timebase)).transpose()?;
+        if let Some(segments) = &segments {
+            segments.configure(&mut cfg, opts.scene_cut_key_frames);
+        }
+
+        let two_pass_stats = opts.two_pass_stats.take();
+        if opts.first_pass {
+            if two_pass_stats.is_some() {
+                return Err(Error::Invalid("first_pass and two_pass_stats are exclusive".into()));
+            }
+
+            cfg.g_pass = vpx_enc_pass::VPX_RC_FIRST_PASS;
+        }
+
+        // The TPL stats come through the external rate control, which libvpx
+        // only runs in the last pass
+        if opts.tpl {
+            if id != CodecID::VP9 {
+                return Err(unsupported_error!("tpl"));
+            }
+            if two_pass_stats.is_none() {
+                return Err(Error::Invalid("tpl requires the two_pass_stats option".into()));
+            }
+        }
+
+        if let Some(stats) = &two_pass_stats {
+            cfg.g_pass = vpx_enc_pass::VPX_RC_LAST_PASS;
+            cfg.rc_twopass_stats_in = vpx_fixed_buf {
+                buf: stats.as_ptr() as *mut _,
+                sz: stats.len(),
+            };
+        }
+
+        if id == CodecID::VP9 {
+            cfg.g_profile = params.encoder.profile.map_or_else(|| vp9_profile(img_fmt), |profile| profile as c_uint);
+            cfg.g_bit_depth = vpx_bit_depth(bit_depth);
+            cfg.g_input_bit_depth = bit_depth;
+        }
+
+        if id == CodecID::VP8 && opts.scalability_mode.spatial_layers() > 1 {
+            return Err(unsupported_error!(opts.scalability_mode));
+        }
+
+        let target_level = match id {
+            CodecID::VP8 if opts.target_level.is_some() => return Err(unsupported_error!("target_level")),
+            CodecID::VP8 => None,
+            _ => opts.target_level.or(params.encoder.level.map(|level| level as u32)),
+        };
+
+        if let Some(target_level) = target_level {
+            let frame_rate = params.video.frame_rate.map(|frame_rate| *frame_rate.numer() as f64 / *frame_rate.denom() as f64);
+            let bit_rate = params.encoder.bit_rate.map(|bit_rate| (bit_rate / 1000) as u32);
+
+            level::validate_level(target_level, cfg.g_w, cfg.g_h, frame_rate, bit_rate)?;
+        }
+
+        let layers = LayerStructure::new(opts.scalability_mode);
+        if let Some(layers) = &layers {
+            if opts.long_term_reference {
+                return Err(unsupported_error!("long_term_reference"));
+            }
+
+            layers.configure(id, &mut cfg);
+        }
+
+        if opts.long_term_reference {
+            cfg.g_lag_in_frames = 0;
+
+            if id == CodecID::VP9 {
+                ltr::configure_vp9(&mut cfg);
+            }
+        }
+
+        let (cpu_used, deadline) = match opts.speed {
+            Some(speed) => {
+                let (cpu_used, deadline) = speed.settings(id);
+                (Some(cpu_used), deadline)
+            }
+            None if layers.is_some() => (None, VPX_DL_REALTIME as vpx_enc_deadline_t),
+            None => (None, VPX_DL_GOOD_QUALITY as vpx_enc_deadline_t),
+        };
+        let cpu_used = opts.cpu_used.or(cpu_used);
+        let deadline = opts.deadline.unwrap_or(deadline);
+
+        let (threads, _) = opts.threading.resolve(id, cfg.g_w, cfg.g_h);
+        if let Some(threads) = threads {
+            cfg.g_threads = threads;
+        }
+
+        let mut flags = 0;
+        if bit_depth > 8 {
+            flags |= VPX_CODEC_USE_HIGHBITDEPTH as vpx_codec_flags_t;
+        }
+        if opts.stats {
+            flags |= VPX_CODEC_USE_PSNR as vpx_codec_flags_t;
+        }
+        if opts.output_partitions {
+            if id != CodecID::VP8 {
+                return Err(unsupported_error!("output_partitions"));
+            }
+            flags |= VPX_CODEC_USE_OUTPUT_PARTITION as vpx_codec_flags_t;
+        }
+
+        let mut ctx = MaybeUninit::uninit();
+        let ver = VPX_ENCODER_ABI_VERSION as i32;
+        let ret = unsafe { vpx_sys::vpx_codec_enc_init_ver(ctx.as_mut_ptr(), iface, &cfg, flags, ver) };
+
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        let mut encoder = Self {
+            id,
+            name,
+            ctx: unsafe { ctx.assume_init() },
+            cfg,
+            img_fmt,
+            bit_depth,
+            deadline,
+            frame_count: 0,
+            timestamps: Timestamps::new(cfg.g_timebase, params.video.frame_rate),
+            layers,
+            ltr: opts.long_term_reference.then(LongTermReferences::default),
+            target_level,
+            segments,
+            packets: VecDeque::new(),
+            packet_info: None,
+            pending_frames: VecDeque::new(),
+            frame_drop: FrameDropOptions::default(),
+            coded_size: None,
+            active_map: None,
+            external_rate_control: opts.external_rate_control,
+            stats: opts.stats,
+            report_dropped_frames: opts.report_dropped_frames,
+            color: opts.color,
+            color_config: None,
+            two_pass_stats,
+            rate_controller: None,
+            tpl_stats: None,
+            first_pass_data: Vec::new(),
+            first_pass_stats: Vec::new(),
+        };
+
+        if id == CodecID::VP9 {
+            if let Some(layers) = &encoder.layers {
+                let mut svc_params = layers.svc_parameters(&encoder.cfg);
+                let spatial_layers = layers.mode().spatial_layers();
+                let inter_layer_pred = layers.inter_layer_pred();
+
+                encoder.control(vp8e_enc_control_id::VP9E_SET_SVC, 1)?;
+                encoder.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_PARAMETERS, &mut svc_params)?;
+
+                if spatial_layers > 1 {
+                    encoder.control(vp8e_enc_control_id::VP9E_SET_SVC_INTER_LAYER_PRED, inter_layer_pred)?;
+                }
+            }
+
+            if encoder.ltr.is_some() {
+                let mut svc_params = ltr::vp9_svc_parameters(&encoder.cfg);
+
+                encoder.control(vp8e_enc_control_id::VP9E_SET_SVC, 1)?;
+                encoder.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_PARAMETERS, &mut svc_params)?;
+            }
+        }
+
+        if opts.external_rate_control {
+            let control = match id {
+                CodecID::VP8 => vp8e_enc_control_id::VP8E_SET_RTC_EXTERNAL_RATECTRL,
+                _ => vp8e_enc_control_id::VP9E_SET_RTC_EXTERNAL_RATECTRL,
+            };
+            encoder.control(control, 1)?;
+        }
+
+        if let Some(cpu_used) = cpu_used {
+            encoder.set_cpu_used(cpu_used)?;
+        }
+
+        encoder.set_tuning(&opts.tuning())?;
+        encoder.set_threading(&opts.threading)?;
+        encoder.set_quality(&opts.quality)?;
+        encoder.set_frame_drop(&opts.frame_drop)?;
+
+        if let Some(scaling_mode) = opts.resize.scaling_mode {
+            encoder.set_scaling_mode(scaling_mode)?;
+        }
+
+        if let Some(lossless) = opts.lossless {
+            encoder.set_lossless(lossless)?;
+        }
+
+        if opts.tpl {
+            encoder.enable_tpl_stats()?;
+        }
+
+        let level_stats = (id == CodecID::VP9 && opts.level_stats).then_some(level::LEVEL_STATS_ONLY);
+        if let Some(target_level) = target_level.or(level_stats) {
+            encoder.control(vp8e_enc_control_id::VP9E_SET_TARGET_LEVEL, target_level as c_int)?;
+        }
+
+        Ok(encoder)
+    }
+
+    pub fn packet_info(&self) -> Option<&PacketInfo> {
+        self.packet_info.as_ref()
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) -> Result<()> {
+        let (cpu_used, deadline) = speed.settings(self.id);
+
+        self.set_cpu_used(cpu_used)?;
+        self.deadline = deadline;
+
+        Ok(())
+    }
+
+    // Raw VP8E_SET_CPUUSED value, -16 to 16 for VP8 and -9 to 9 for VP9
+    pub fn set_cpu_used(&mut self, cpu_used: i32) -> Result<()> {
+        speed::validate_cpu_used(self.id, cpu_used)?;
+
+        self.control(vp8e_enc_control_id::VP8E_SET_CPUUSED, cpu_used)
+    }
+
+    // Raw time budget of each encode call in microseconds, VPX_DL_REALTIME,
+    // VPX_DL_GOOD_QUALITY or VPX_DL_BEST_QUALITY select the encoding mode
+    pub fn set_deadline(&mut self, deadline: u64) {
+        self.deadline = deadline as vpx_enc_deadline_t;
+    }
+
+    // Override the color signalling taken from the input frames, VP9 only
+    pub fn set_color_signalling(&mut self, color: ColorSignalling) {
+        self.color = color;
+    }
+
+    fn set_color_config(&mut self, config: VpxColorConfig) -> Result<()> {
+        if self.color_config == Some(config) {
+            return Ok(());
+        }
+
+        let mut render_size = [config.render_size.0 as c_int, config.render_size.1 as c_int];
+
+        self.control(vp8e_enc_control_id::VP9E_SET_COLOR_SPACE, config.color_space as c_int)?;
+        self.control(vp8e_enc_control_id::VP9E_SET_COLOR_RANGE, config.color_range as c_int)?;
+        self.control_ptr(vp8e_enc_control_id::VP9E_SET_RENDER_SIZE, render_size.as_mut_ptr())?;
+        self.color_config = Some(config);
+
+        Ok(())
+    }
+
+    // VP9 level of the stream encoded so far, requires a target level or the
+    // level_stats option
+    pub fn level(&mut self) -> Result<u32> {
+        if self.id != CodecID::VP9 {
+            return Err(unsupported_error!("level"));
+        }
+
+        let mut level: c_int = 0;
+        self.control_ptr(vp8e_enc_control_id::VP9E_GET_LEVEL, &mut level)?;
+
+        Ok(level as u32)
+    }
+
+    // VP9 only, the quantizer is forced to 0 so that the decoded frames match
+    // the input exactly
+    pub fn set_lossless(&mut self, lossless: bool) -> Result<()> {
+        if self.id != CodecID::VP9 {
+            return Err(unsupported_error!("lossless"));
+        }
+
+        self.control(vp8e_enc_control_id::VP9E_SET_LOSSLESS, lossless as c_int)
+    }
+
+    // Apply the controls set in tuning, the others are left unchanged
+    pub fn set_tuning(&mut self, tuning: &TuningOptions) -> Result<()> {
+        let vp8 = self.id == CodecID::VP8;
+
+        if let Some(content) = tuning.content {
+            if vp8 {
+                if tuning.screen_content_mode.is_none() {
+                    self.control(vp8e_enc_control_id::VP8E_SET_SCREEN_CONTENT_MODE, (content == ContentType::Screen) as c_int)?;
+                }
+            } else {
+                let content = match content {
+                    ContentType::Default => vp9e_tune_content::VP9E_CONTENT_DEFAULT,
+                    ContentType::Screen => vp9e_tune_content::VP9E_CONTENT_SCREEN,
+                    ContentType::Film => vp9e_tune_content::VP9E_CONTENT_FILM,
+                };
+                self.control(vp8e_enc_control_id::VP9E_SET_TUNE_CONTENT, content as c_int)?;
+            }
+        }
+
+        if let Some(screen_content_mode) = tuning.screen_content_mode.filter(|_| vp8) {
+            self.control(vp8e_enc_control_id::VP8E_SET_SCREEN_CONTENT_MODE, screen_content_mode as c_int)?;
+        }
+
+        if let Some(tuning) = tuning.tuning {
+            let tuning = match tuning {
+                Tuning::Psnr => vp8e_tuning::VP8_TUNE_PSNR,
+                Tuning::Ssim => vp8e_tuning::VP8_TUNE_SSIM,
+            };
+            self.control(vp8e_enc_control_id::VP8E_SET_TUNING, tuning as c_int)?;
+        }
+
+        if let Some(sharpness) = tuning.sharpness {
+            self.control(vp8e_enc_control_id::VP8E_SET_SHARPNESS, sharpness as c_int)?;
+        }
+
+        if let Some(noise_sensitivity) = tuning.noise_sensitivity {
+            if vp8 {
+                self.control(vp8e_enc_control_id::VP8E_SET_NOISE_SENSITIVITY, noise_sensitivity as c_int)?;
+            } else {
+                // VP9 only turns the denoiser on or off
+                if noise_sensitivity > 1 {
+                    return Err(invalid_param_error!(noise_sensitivity));
+                }
+                self.control(vp8e_enc_control_id::VP9E_SET_NOISE_SENSITIVITY, noise_sensitivity as c_int)?;
+            }
+        }
+
+        if let Some(static_threshold) = tuning.static_threshold {
+            self.control(vp8e_enc_control_id::VP8E_SET_STATIC_THRESHOLD, static_threshold as c_int)?;
+        }
+
+        Ok(())
+    }
+
+    // Apply the threading options set, tile options only apply to VP9
+    pub fn set_threading(&mut self, threading: &ThreadingOptions) -> Result<()> {
+        let (threads, tile_columns) = threading.resolve(self.id, self.cfg.g_w, self.cfg.g_h);
+
+        if let Some(threads) = threads.filter(|threads| *threads != self.cfg.g_threads) {
+            self.cfg.g_threads = threads;
+            self.config_set()?;
+        }
+
+        if self.id == CodecID::VP8 {
+            return Ok(());
+        }
+
+        if let Some(tile_columns) = tile_columns {
+            self.control(vp8e_enc_control_id::VP9E_SET_TILE_COLUMNS, tile_columns as c_int)?;
+        }
+
+        if let Some(tile_rows) = threading.tile_rows {
+            self.control(vp8e_enc_control_id::VP9E_SET_TILE_ROWS, tile_rows as c_int)?;
+        }
+
+        if let Some(row_mt) = threading.row_mt {
+            self.control(vp8e_enc_control_id::VP9E_SET_ROW_MT, row_mt as c_int)?;
+        }
+
+        if let Some(frame_parallel_decoding) = threading.frame_parallel_decoding {
+            self.control(vp8e_enc_control_id::VP9E_SET_FRAME_PARALLEL_DECODING, frame_parallel_decoding as c_int)?;
+        }
+
+        Ok(())
+    }
+
+    // Apply the quality controls set, VP9 only controls are ignored by VP8
+    pub fn set_quality(&mut self, quality: &QualityOptions) -> Result<()> {
+        if let Some(auto_alt_ref) = quality.auto_alt_ref {
+            self.control(vp8e_enc_control_id::VP8E_SET_ENABLEAUTOALTREF, auto_alt_ref as c_int)?;
+        }
+
+        if let Some(arnr_max_frames) = quality.arnr_max_frames {
+            self.control(vp8e_enc_control_id::VP8E_SET_ARNR_MAXFRAMES, arnr_max_frames as c_int)?;
+        }
+
+        if let Some(arnr_strength) = quality.arnr_strength {
+            self.control(vp8e_enc_control_id::VP8E_SET_ARNR_STRENGTH, arnr_strength as c_int)?;
+        }
+
+        if let Some(arnr_type) = quality.arnr_type {
+            self.control(vp8e_enc_control_id::VP8E_SET_ARNR_TYPE, arnr_type as c_int)?;
+        }
+
+        if self.id == CodecID::VP8 {
+            return Ok(());
+        }
+
+        if let Some(aq_mode) = quality.aq_mode {
+            self.control(vp8e_enc_control_id::VP9E_SET_AQ_MODE, aq_mode as c_int)?;
+        }
+
+        if let Some(alt_ref_aq) = quality.alt_ref_aq {
+            self.control(vp8e_enc_control_id::VP9E_SET_ALT_REF_AQ, alt_ref_aq as c_int)?;
+        }
+
+        if let Some(min_gf_interval) = quality.min_gf_interval {
+            self.control(vp8e_enc_control_id::VP9E_SET_MIN_GF_INTERVAL, min_gf_interval as c_int)?;
+        }
+
+        if let Some(max_gf_interval) = quality.max_gf_interval {
+            self.control(vp8e_enc_control_id::VP9E_SET_MAX_GF_INTERVAL, max_gf_interval as c_int)?;
+        }
+
+        if let Some(frame_periodic_boost) = quality.frame_periodic_boost {
+            self.control(vp8e_enc_control_id::VP9E_SET_FRAME_PERIODIC_BOOST, frame_periodic_boost as c_int)?;
+        }
+
+        if let Some(delta_q_uv) = quality.delta_q_uv {
+            self.control(vp8e_enc_control_id::VP9E_SET_DELTA_Q_UV, delta_q_uv)?;
+        }
+
+        Ok(())
+    }
+
+    // QP of the following frames on the 0 to 63 scale, requires the
+    // external_rate_control option which turns the libvpx rate control off
+    pub fn set_frame_qp(&mut self, qp: u32) -> Result<()> {
+        if !self.external_rate_control {
+            return Err(Error::Invalid("frame QP requires external rate control".into()));
+        }
+
+        if qp > 63 {
+            return Err(invalid_param_error!(qp));
+        }
+
+        if self.id == CodecID::VP9 {
+            return self.control(vp8e_enc_control_id::VP9E_SET_QUANTIZER_ONE_PASS, qp as c_int);
+        }
+
+        if self.cfg.rc_min_quantizer == qp && self.cfg.rc_max_quantizer == qp {
+            return Ok(());
+        }
+
+        self.cfg.rc_min_quantizer = qp;
+        self.cfg.rc_max_quantizer = qp;
+        self.config_set()
+    }
+
+    // The next frame refreshes a long-term reference, requires the
+    // long_term_reference option
+    pub fn mark_long_term_reference(&mut self) -> Result<()> {
+        self.long_term_references()?.mark();
+        Ok(())
+    }
+
+    // The receiver got the long-term reference of the frame with the pts
+    pub fn acknowledge_long_term_reference(&mut self, pts: i64) -> Result<()> {
+        self.long_term_references()?.acknowledge(pts);
+        Ok(())
+    }
+
+    // After a loss the next frame predicts only from the last acknowledged
+    // long-term reference, or is a keyframe when there is none
+    pub fn request_recovery(&mut self) -> Result<()> {
+        self.long_term_references()?.request_recovery();
+        Ok(())
+    }
+
+    fn long_term_references(&mut self) -> Result<&mut LongTermReferences> {
+        self.ltr.as_mut().ok_or_else(|| Error::Invalid("long-term references require the long_term_reference option".into()))
+    }
+
+    // Apply the resize options set
+    pub fn set_resize(&mut self, resize: &ResizeOptions) -> Result<()> {
+        if resize.resize_allowed.is_some() || resize.up_threshold.is_some() || resize.down_threshold.is_some() || resize.scaled_size.is_some() {
+            resize.apply(&mut self.cfg);
+            self.config_set()?;
+        }
+
+        if let Some(scaling_mode) = resize.scaling_mode {
+            self.set_scaling_mode(scaling_mode)?;
+        }
+
+        Ok(())
+    }
+
+    // Scale the following frames by the ratio, VP8 codes a keyframe at each
+    // change
+    pub fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) -> Result<()> {
+        let mut vpx_scaling_mode = vpx_scaling_mode {
+            h_scaling_mode: scaling_mode.into(),
+            v_scaling_mode: scaling_mode.into(),
+        };
+
+        self.control_ptr(vp8e_enc_control_id::VP8E_SET_SCALEMODE, &mut vpx_scaling_mode)
+    }
+
+    // Apply the frame drop options set, VP9 only controls are ignored by VP8
+    pub fn set_frame_drop(&mut self, frame_drop: &FrameDropOptions) -> Result<()> {
+        self.frame_drop.merge(frame_drop);
+
+        if let Some(threshold) = frame_drop.threshold.filter(|threshold| *threshold != self.cfg.rc_dropframe_thresh) {
+            self.cfg.rc_dropframe_thresh = threshold;
+            self.config_set()?;
+        }
+
+        if self.id == CodecID::VP8 {
+            return Ok(());
+        }
+
+        if let Some(postencode_drop) = frame_drop.postencode_drop {
+            self.control(vp8e_enc_control_id::VP9E_SET_POSTENCODE_DROP, postencode_drop as c_int)?;
+        }
+
+        if let Some(disable_overshoot_maxq_cbr) = frame_drop.disable_overshoot_maxq_cbr {
+            self.control(vp8e_enc_control_id::VP9E_SET_DISABLE_OVERSHOOT_MAXQ_CBR, disable_overshoot_maxq_cbr as c_int)?;
+        }
+
+        let layer_drop = frame_drop.threshold.is_some() || frame_drop.layer_drop_mode.is_some() || frame_drop.max_consecutive_drops.is_some();
+        if self.layers.is_some() && layer_drop {
+            // Every spatial layer drops at the same buffer level
+            let mut svc_frame_drop = vpx_svc_frame_drop {
+                framedrop_thresh: [self.cfg.rc_dropframe_thresh as c_int; VPX_SS_MAX_LAYERS as usize],
+                framedrop_mode: self.frame_drop.layer_drop_mode.unwrap_or(LayerDropMode::Constrained).into(),
+                max_consec_drop: self.frame_drop.max_consecutive_drops.unwrap_or(c_int::MAX),
+            };
+
+            self.control_ptr(vp8e_enc_control_id::VP9E_SET_SVC_FRAME_DROP_LAYER, &mut svc_frame_drop)?;
+        }
+
+        Ok(())
+    }
+
+    // Run a first pass over the frames, the encoder options apply except those
+    // of the other passes
+    pub fn first_pass<I>(id: CodecID, params: &VideoEncoderParameters, options: Option<&Variant>, frames: I) -> Result<FirstPass>
+    where
+        I: IntoIterator<Item = SharedFrame<VideoFrame<'static>>>,
+    {
+        let mut options = options.cloned().unwrap_or_else(Variant::new_dict);
+        options.dict_set("first_pass", true.into());
+
+        let mut encoder = Self::new(id, params, Some(&options))?;
+        let config = VideoEncoder {
+            video: params.video.clone(),
+            encoder: params.encoder.clone(),
+        };
+
+        for frame in frames {
+            encoder.send_frame(&config, None, frame)?;
+        }
+        encoder.flush(&config)?;
+
+        // libvpx appends the totals to the stats when the pass ends
+        let mut frames = mem::take(&mut encoder.first_pass_stats);
+        let total = frames.pop();
+
+        Ok(FirstPass {
+            frames,
+            total,
+            data: mem::take(&mut encoder.first_pass_data),
+        })
+    }
+
+    // Raw stats of the first pass so far, requires the first_pass option
+    pub fn first_pass_data(&self) -> &[u8] {
+        &self.first_pass_data
+    }
+
+    // Stats of each frame of the first pass so far, VP9 only
+    pub fn first_pass_stats(&self) -> &[FirstPassStats] {
+        &self.first_pass_stats
+    }
+
+    // Hand the VP9 rate decisions to the controller, libvpx only consults it in
+    // the last pass of two-pass encoding, so the encoder must be created with
+    // the two_pass_stats option and no frame sent yet
+    pub fn set_rate_controller(&mut self, controller: Box<dyn RateController>) -> Result<()> {
+        if self.id != CodecID::VP9 {
+            return Err(unsupported_error!("rate_controller"));
+        }
+
+        if self.two_pass_stats.is_none() || self.frame_count > 0 {
+            return Err(Error::Invalid("external rate control requires the last pass before the first frame".into()));
+        }
+
+        // Keep collecting the TPL stats for the tpl_stats method
+        let controller: Box<dyn RateController> = match &self.tpl_stats {
+            Some(queue) => Box::new(TplStatsCollector {
+                queue: queue.clone(),
+                controller: Some(controller),
+            }),
+            None => controller,
+        };
+
+        let mut controller = Box::new(controller);
+        let mut funcs = rate_control::rate_control_funcs(&mut controller);
+
+        self.control_ptr(vp8e_enc_control_id::VP9E_SET_EXTERNAL_RATE_CONTROL, &mut funcs)?;
+        self.rate_controller = Some(controller);
+
+        Ok(())
+    }
+
+    // Enable the VP9 temporal dependency model and queue its stats for each GOP,
+    // they are only computed in the last pass of two-pass encoding. A rate
+    // controller set afterwards receives the stats as well.
+    fn enable_tpl_stats(&mut self) -> Result<()> {
+        self.control(vp8e_enc_control_id::VP9E_SET_TPL, 1)?;
+
+        let queue = TplStatsQueue::default();
+        self.set_rate_controller(Box::new(TplStatsCollector {
+            queue: queue.clone(),
+            controller: None,
+        }))?;
+        self.tpl_stats = Some(queue);
+
+        Ok(())
+    }
+
+    // Pop the TPL stats of the next GOP, requires the tpl option
+    pub fn tpl_stats(&mut self) -> Option<TplGopStats> {
+        self.tpl_stats.as_ref()?.lock().ok()?.pop_front()
+    }
+
+    // Size in pixels of the blocks addressed by ROI maps
+    pub fn roi_block_size(&self) -> u32 {
+        if self.id == CodecID::VP8 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // Number of columns and rows of ROI maps
+    pub fn roi_map_size(&self) -> (u32, u32) {
+        let block_size = self.roi_block_size();
+
+        (self.cfg.g_w.div_ceil(block_size), self.cfg.g_h.div_ceil(block_size))
+    }
+
+    // Apply the ROI map to the following frames, None disables it. VP9 only
+    // applies it with the realtime deadline and ignores it otherwise.
+    pub fn set_roi_map(&mut self, roi_map: Option<&RoiMap>) -> Result<()> {
+        let (cols, rows) = self.roi_map_size();
+        let (control, max_segments, max_delta_q) = if self.id == CodecID::VP8 {
+            (vp8e_enc_control_id::VP8E_SET_ROI_MAP, 4, 63)
+        } else {
+            (vp8e_enc_control_id::VP9E_SET_ROI_MAP, 8, 255)
+        };
+
+        let mut vpx_roi_map = vpx_roi_map_t {
+            enabled: 0,
+            roi_map: ptr::null_mut(),
+            rows,
+            cols,
+            delta_q: [0; 8],
+            delta_lf: [0; 8],
+            skip: [0; 8],
+            ref_frame: [-1; 8],
+            static_threshold: [0; 4],
+        };
+
+        // libvpx copies the segment map, so it only needs to outlive the control
+        let mut map;
+
+        if let Some(roi_map) = roi_map {
+            roi_map.validate(cols, rows, max_segments, max_delta_q)?;
+
+            map = roi_map.map.clone();
+            vpx_roi_map.enabled = 1;
+            vpx_roi_map.roi_map = map.as_mut_ptr();
+
+            for (i, segment) in roi_map.segments.iter().enumerate() {
+                vpx_roi_map.delta_q[i] = segment.delta_q;
+                vpx_roi_map.delta_lf[i] = segment.delta_lf;
+                vpx_roi_map.skip[i] = segment.skip as c_int;
+                vpx_roi_map.ref_frame[i] = ReferenceFrame::as_vpx(segment.ref_frame);
+
+                if i < vpx_roi_map.static_threshold.len() {
+                    vpx_roi_map.static_threshold[i] = segment.static_threshold;
+                }
+            }
+        }
+
+        self.control_ptr(control, &mut vpx_roi_map)
+    }
+
+    // Number of columns and rows of active maps
+    pub fn active_map_size(&self) -> (u32, u32) {
+        (self.cfg.g_w.div_ceil(ACTIVE_MAP_BLOCK_SIZE), self.cfg.g_h.div_ceil(ACTIVE_MAP_BLOCK_SIZE))
+    }
+
+    // Encode only the active blocks of the following frames, None makes every
+    // block active again
+    pub fn set_active_map(&mut self, active_map: Option<&ActiveMap>) -> Result<()> {
+        let (cols, rows) = self.active_map_size();
+
+        let mut vpx_active_map = vpx_active_map_t {
+            active_map: ptr::null_mut(),
+            rows,
+            cols,
+        };
+
+        let mut map;
+
+        if let Some(active_map) = active_map {
+            active_map.validate(cols, rows)?;
+
+            map = active_map.map.clone();
+            vpx_active_map.active_map = map.as_mut_ptr();
+        }
+
+        self.control_ptr(vp8e_enc_control_id::VP8E_SET_ACTIVEMAP, &mut vpx_active_map)?;
+        self.active_map = active_map.cloned();
+
+        Ok(())
+    }
+
+    // VP9 reports the map in use by the encoder, VP8 the last map set
+    pub fn active_map(&mut self) -> Result<ActiveMap> {
+        let (cols, rows) = self.active_map_size();
+
+        if self.id == CodecID::VP8 {
+            return Ok(self.active_map.clone().unwrap_or_else(|| ActiveMap {
+                cols,
+                rows,
+                map: vec![1; (cols * rows) as usize],
+            }));
+        }
+
+        let mut active_map = ActiveMap::new(cols, rows);
+        let mut vpx_active_map = vpx_active_map_t {
+            active_map: active_map.map.as_mut_ptr(),
+            rows,
+            cols,
+        };
+
+        self.control_ptr(vp8e_enc_control_id::VP9E_GET_ACTIVEMAP, &mut vpx_active_map)?;
+
+        Ok(active_map)
+    }
+
+    fn control(&mut self, id: vp8e_enc_control_id, value: c_int) -> Result<()> {
+        let ret = unsafe { vpx_sys::vpx_codec_control_(&mut self.ctx, id as c_int, value) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
+    }
+
+    fn control_ptr<T>(&mut self, id: vp8e_enc_control_id, value: *mut T) -> Result<()> {
+        let ret = unsafe { vpx_sys::vpx_codec_control_(&mut self.ctx, id as c_int, value) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
+    }
+
+    fn config_set(&mut self) -> Result<()> {
+        let ret = unsafe { vpx_sys::vpx_codec_enc_config_set(&mut self.ctx, &self.cfg) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
+    }
+
+    fn set_bit_rate(&mut self, bit_rate: u64) -> Result<()> {
+        if let Some(target_level) = self.target_level {
+            level::validate_level(target_level, self.cfg.g_w, self.cfg.g_h, None, Some((bit_rate / 1000) as u32))?;
+        }
+
+        self.cfg.rc_target_bitrate = (bit_rate / 1000) as c_uint;
+        if let Some(layers) = &self.layers {
+            layers.configure_bitrate(&mut self.cfg);
+        } else if self.id == CodecID::VP9 && self.ltr.is_some() {
+            ltr::configure_vp9_bitrate(&mut self.cfg);
+        }
+
+        self.config_set()
+    }
+
+    fn time_base(&self) -> Rational64 {
+        Rational64::new(self.cfg.g_timebase.num as i64, self.cfg.g_timebase.den as i64)
+    }
+
+    // Queue the frame packets produced by the last encode call, returns whether a
+    // keyframe was produced
+    fn get_packets(&mut self, pool: Option<&Arc<BufferPool>>) -> Result<bool> {
+        let time_base = self.time_base();
+        let mut key_frame = false;
+        let mut iter: vpx_codec_iter_t = ptr::null();
+        let mut last_index = None;
+        let mut pending_psnr = None;
+
+        loop {
+            let pkt = unsafe { vpx_sys::vpx_codec_get_cx_data(&mut self.ctx, &mut iter) };
+            if pkt.is_null() {
+                break;
+            }
+
+            let pkt = unsafe { &*pkt };
+
+            // libvpx emits the PSNR packet before the frame packet it measures,
+            // so it is held in pending_psnr for the next frame packet, only a
+            // previous frame packet still lacking its PSNR takes it directly
+            if pkt.kind == vpx_codec_cx_pkt_kind::VPX_CODEC_PSNR_PKT {
+                let psnr = Psnr::from(unsafe { &pkt.data.psnr });
+                let stats = last_index.and_then(|index| self.packets.get_mut(index)).and_then(|(_, info)| info.stats.as_mut());
+
+                match stats {
+                    Some(stats) if stats.psnr.is_none() => stats.psnr = Some(psnr),
+                    _ => pending_psnr = Some(psnr),
+                }
+
+                continue;
+            }
+
+            if pkt.kind == vpx_codec_cx_pkt_kind::VPX_CODEC_STATS_PKT {
+                let buf = unsafe { &pkt.data.twopass_stats };
+                let data = unsafe { slice::from_raw_parts(buf.buf as *const u8, buf.sz) };
+
+                self.first_pass_data.extend_from_slice(data);
+                if self.id == CodecID::VP9 {
+                    self.first_pass_stats.push(FirstPassStats::from_packet(data)?);
+                }
+
+                continue;
+            }
+
+            if pkt.kind != vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT {
+                continue;
+            }
+
+            let frame = unsafe { &pkt.data.frame };
+            let data = unsafe { slice::from_raw_parts(frame.buf as *const u8, frame.sz) };
+
+            let mut packet = new_packet(data, pool);
+            // Visible frames are output in display order and invisible ones
+            // before the frame they precede, so the dts follows the pts, see
+            // PacketInfo::invisible for the dts of invisible frames
+            packet.pts = Some(frame.pts);
+            packet.dts = Some(frame.pts);
+            packet.duration = Some(frame.duration as i64);
+            packet.time_base = Some(time_base);
+
+            // VP9 packs the invisible alt-ref frames into the superframe of the
+            // next visible frame, VP8 has no superframes and outputs them alone
+            // with no duration
+            let mut info = if frame.flags & VPX_FRAME_IS_INVISIBLE != 0 {
+                PacketInfo::default()
+            } else if frame.partition_id > 0 {
+                // Later partitions belong to the frame of the previous packet
+                last_index
+                    .and_then(|index| self.packets.get(index))
+                    .map(|(_, info)| PacketInfo {
+                        stats: None,
+                        ..info.clone()
+                    })
+                    .unwrap_or_default()
+            } else {
+                self.pending_frame_info(pool, frame.pts)
+            };
+
+            if self.layers.as_ref().is_some_and(|layers| layers.mode().spatial_layers() > 1) {
+                info.spatial_layers = spatial_layer_info(frame, data);
+            }
+
+            info.coded_size = if self.id == CodecID::VP8 {
+                if frame.flags & VPX_FRAME_IS_KEY != 0 {
+                    self.coded_size = resize::vp8_key_frame_size(data).or(self.coded_size);
+                }
+                self.coded_size
+            } else {
+                // The size of the highest spatial layer
+                (0..VPX_SS_MAX_LAYERS as usize).rev().map(|sl| (frame.width[sl], frame.height[sl])).find(|(width, _)| *width != 0)
+            };
+
+            set_frame_flags(frame, &mut packet, &mut info);
+
+            if frame.flags & VPX_FRAME_IS_KEY != 0 {
+                key_frame = true;
+
+                if info.temporal_layer_id.is_some() {
+                    info.temporal_layer_id = Some(0);
+                    info.layer_sync = true;
+                }
+
+                info.long_term_reference = self.ltr.is_some();
+            }
+
+            if self.stats || self.external_rate_control {
+                let mut stats = self.frame_stats(frame);
+                stats.psnr = pending_psnr.take();
+                info.stats = Some(stats);
+            }
+
+            self.packets.push_back((packet, info));
+            last_index = Some(self.packets.len() - 1);
+        }
+
+        Ok(key_frame)
+    }
+
+    // Take the info of the frame output with the pts, the frames sent before it
+    // were skipped by the encoder
+    fn pending_frame_info(&mut self, pool: Option<&Arc<BufferPool>>, pts: i64) -> PacketInfo {
+        while let Some((pending_pts, duration, info)) = self.pending_frames.pop_front() {
+            if pending_pts >= pts {
+                if pending_pts == pts {
+                    return info;
+                }
+
+                self.pending_frames.push_front((pending_pts, duration, info));
+                break;
+            }
+
+            self.push_dropped(pool, pending_pts, duration, info);
+        }
+
+        PacketInfo::default()
+    }
+
+    // Dropped frames are reported as empty packets on request only, as they are
+    // not valid samples for muxers and decoders
+    fn push_dropped(&mut self, pool: Option<&Arc<BufferPool>>, pts: i64, duration: i64, mut info: PacketInfo) {
+        if !self.report_dropped_frames {
+            return;
+        }
+
+        let mut packet = new_packet(&[], pool);
+        packet.pts = Some(pts);
+        packet.dts = Some(pts);
+        packet.duration = Some(duration);
+        packet.time_base = Some(self.time_base());
+
+        info.dropped = true;
+
+        self.packets.push_back((packet, info));
+    }
+
+    fn frame_stats(&mut self, frame: &vpx_sys::vpx_codec_cx_pkt__bindgen_ty_1__bindgen_ty_1) -> EncodeStats {
+        let mut stats = EncodeStats {
+            size: frame.sz,
+            key_frame: frame.flags & VPX_FRAME_IS_KEY != 0,
+            droppable: frame.flags & VPX_FRAME_IS_DROPPABLE != 0,
+            ..Default::default()
+        };
+
+        let mut quantizer: c_int = 0;
+        if self.control_ptr(vp8e_enc_control_id::VP8E_GET_LAST_QUANTIZER_64, &mut quantizer).is_ok() {
+            stats.quantizer = Some(quantizer);
+        }
+
+        if self.id == CodecID::VP8 {
+            return stats;
+        }
+
+        let mut loop_filter_level: c_int = 0;
+        if self.control_ptr(vp8e_enc_control_id::VP9E_GET_LOOPFILTER_LEVEL, &mut loop_filter_level).is_ok() {
+            stats.loop_filter_level = Some(loop_filter_level);
+        }
+
+        let layer_count = self.layers.as_ref().map_or(0, |layers| layers.spatial_layers() * layers.temporal_layers());
+        if layer_count > 0 {
+            let mut quantizers = [0 as c_int; VPX_MAX_LAYERS as usize];
+            if self.control_ptr(vp8e_enc_control_id::VP9E_GET_LAST_QUANTIZER_SVC_LAYERS, quantizers.as_mut_ptr()).is_ok() {
+                stats.layer_quantizers = quantizers[..layer_count].to_vec();
+            }
+        }
+
+        stats
+    }
+}
+
+// The image points to the planes of the frame, which must outlive it
+fn wrap_image(desc: &VideoFrameDescriptor, planes: &MappedPlanes, img_fmt: vpx_img_fmt, bit_depth: u32) -> Result<vpx_image_t> {
+    let mut img = MaybeUninit::<vpx_image_t>::zeroed();
+    let plane_data = planes.plane_data(0).ok_or_else(|| Error::Invalid("invalid frame plane".into()))?;
+    let ret = unsafe { vpx_sys::vpx_img_wrap(img.as_mut_ptr(), img_fmt, desc.width().get(), desc.height().get(), 1, plane_data.as_ptr() as *mut u8) };
+    if ret.is_null() {
+        return Err(Error::Invalid("failed to wrap frame".into()));
+    }
+
+    let mut img = unsafe { img.assume_init() };
+    img.bit_depth = bit_depth;
+
+    for plane in 0..planes.len() {
+        img.planes[plane] = planes.plane_data(plane).map_or(ptr::null_mut(), |data| data.as_ptr() as *mut u8);
+        img.stride[plane] = planes.plane_stride(plane).unwrap_or(0) as c_int;
+    }
+
+    match desc.format {
+        // YV12 stores V before U
+        PixelFormat::YV12 => {
+            img.planes.swap(1, 2);
+            img.stride.swap(1, 2);
+        }
+        PixelFormat::NV12 => {
+            img.planes[2] = unsafe { img.planes[1].add(1) };
+            img.stride[2] = img.stride[1];
+        }
+        _ => {}
+    }
+
+    Ok(img)
+}
+
+// Describe the spatial layers of a VP9 superframe, layers dropped by the rate
+// control are absent from both the superframe and the result
+fn spatial_layer_info(frame: &vpx_sys::vpx_codec_cx_pkt__bindgen_ty_1__bindgen_ty_1, data: &[u8]) -> Vec<SpatialLayerInfo> {
+    let sizes = svc::superframe_sizes(data);
+
+    (0..VPX_SS_MAX_LAYERS as usize)
+        .filter(|sl| frame.spatial_layer_encoded[*sl] != 0)
+        .zip(sizes)
+        .map(|(sl, size)| SpatialLayerInfo {
+            spatial_layer_id: sl as u8,
+            width: frame.width[sl],
+            height: frame.height[sl],
+            size,
+        })
+        .collect()
+}
+
+fn set_frame_flags(frame: &vpx_sys::vpx_codec_cx_pkt__bindgen_ty_1__bindgen_ty_1, packet: &mut Packet, info: &mut PacketInfo) {
+    if frame.flags & VPX_FRAME_IS_KEY != 0 {
+        packet.flags |= PacketFlags::Key;
+    }
+
+    info.discardable = frame.flags & VPX_FRAME_IS_DROPPABLE != 0;
+    info.invisible = frame.flags & VPX_FRAME_IS_INVISIBLE != 0;
+    info.fragment = frame.flags & VPX_FRAME_IS_FRAGMENT != 0;
+    // Negative when the frame is not partitioned
+    info.partition_id = u32::try_from(frame.partition_id).ok();
+}
+
+fn new_packet(data: &[u8], pool: Option<&Arc<BufferPool>>) -> Packet<'static> {
+    if let Some(pool) = pool {
+        let mut buffer = pool.get_buffer_with_length(data.len());
+        if let Some(buffer_mut) = Arc::get_mut(&mut buffer) {
+            buffer_mut.data_mut().copy_from_slice(data);
+            return Packet::from_buffer(buffer);
+        }
+    }
+
+    Packet::from_slice(data).into_owned()
+}
+
+impl Drop for VpxEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            vpx_sys::vpx_codec_destroy(&mut self.ctx);
+        }
+    }
+}
+
+pub struct VpxEncoderBuilder {
+    id: CodecID,
+    name: &'static str,
+}
+
+impl EncoderBuilder<VideoEncoder> for VpxEncoderBuilder {
+    fn new_encoder(&self, codec_id: CodecID, params: &CodecParameters, options: Option<&Variant>) -> Result<Box<dyn Encoder<VideoEncoder>>> {
+        Ok(Box::new(VpxEncoder::new(codec_id, &params.try_into()?, options)?))
+    }
+}
+
+impl CodecBuilder<VideoEncoder> for VpxEncoderBuilder {
+    fn id(&self) -> CodecID {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl CodecInformation for VpxEncoder {
+    fn id(&self) -> CodecID {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+const VP8_CODEC_NAME: &str = "vp8-enc";
+const VP9_CODEC_NAME: &str = "vp9-enc";
+
+const VP8_ENCODER_BUILDER: VpxEncoderBuilder = VpxEncoderBuilder {
+    id: CodecID::VP8,
+    name: VP8_CODEC_NAME,
+};
+
+const VP9_ENCODER_BUILDER: VpxEncoderBuilder = VpxEncoderBuilder {
+    id: CodecID::VP9,
+    name: VP9_CODEC_NAME,
+};
+
+#[ctor]
+pub fn initialize() {
+    register_encoder(Arc::new(VP8_ENCODER_BUILDER), false);
+    register_encoder(Arc::new(VP9_ENCODER_BUILDER), false);
+}
@@ -0,0 +1,143 @@
+use media_core::{error::Error, Result};
+
+// Active maps address 16x16 macroblocks for both VP8 and VP9
+pub(super) const ACTIVE_MAP_BLOCK_SIZE: u32 = 16;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActiveMap {
+    pub cols: u32,
+    pub rows: u32,
+    // Non-zero for blocks to encode, zero for blocks to skip, in raster order
+    pub map: Vec<u8>,
+}
+
+impl ActiveMap {
+    // All blocks are inactive
+    pub fn new(cols: u32, rows: u32) -> Self {
+        Self {
+            cols,
+            rows,
+            map: vec![0; (cols * rows) as usize],
+        }
+    }
+
+    // Mark the blocks of a width x height frame touched by the damaged regions
+    // as active
+    pub fn from_regions(width: u32, height: u32, regions: &[Region]) -> Self {
+        let mut active_map = Self::new(width.div_ceil(ACTIVE_MAP_BLOCK_SIZE), height.div_ceil(ACTIVE_MAP_BLOCK_SIZE));
+
+        for region in regions {
+            active_map.add_region(region);
+        }
+
+        active_map
+    }
+
+    // Scale a damage bitmap of any resolution covering a width x height frame to
+    // the block grid, a block is active if it overlaps a set cell
+    pub fn from_bitmap(width: u32, height: u32, bitmap: &[u8], bitmap_cols: u32, bitmap_rows: u32) -> Result<Self> {
+        if (bitmap_cols as usize).checked_mul(bitmap_rows as usize) != Some(bitmap.len()) {
+            return Err(Error::Invalid("bitmap size does not match its dimensions".into()));
+        }
+
+        let mut active_map = Self::new(width.div_ceil(ACTIVE_MAP_BLOCK_SIZE), height.div_ceil(ACTIVE_MAP_BLOCK_SIZE));
+
+        for (i, _) in bitmap.iter().enumerate().filter(|(_, cell)| **cell != 0) {
+            let col = i as u64 % bitmap_cols as u64;
+            let row = i as u64 / bitmap_cols as u64;
+            let x = col * width as u64 / bitmap_cols as u64;
+            let y = row * height as u64 / bitmap_rows as u64;
+            // Rounding the end up keeps cells smaller than a pixel
+            let x_end = ((col + 1) * width as u64).div_ceil(bitmap_cols as u64);
+            let y_end = ((row + 1) * height as u64).div_ceil(bitmap_rows as u64);
+
+            active_map.add_region(&Region {
+                x: x as u32,
+                y: y as u32,
+                width: (x_end - x) as u32,
+                height: (y_end - y) as u32,
+            });
+        }
+
+        Ok(active_map)
+    }
+
+    pub fn add_region(&mut self, region: &Region) {
+        if region.width == 0 || region.height == 0 {
+            return;
+        }
+
+        let col_start = (region.x / ACTIVE_MAP_BLOCK_SIZE).min(self.cols);
+        let row_start = (region.y / ACTIVE_MAP_BLOCK_SIZE).min(self.rows);
+        let col_end = region.x.saturating_add(region.width).div_ceil(ACTIVE_MAP_BLOCK_SIZE).min(self.cols);
+        let row_end = region.y.saturating_add(region.height).div_ceil(ACTIVE_MAP_BLOCK_SIZE).min(self.rows);
+
+        for row in row_start..row_end {
+            let offset = (row * self.cols) as usize;
+            self.map[offset + col_start as usize..offset + col_end as usize].fill(1);
+        }
+    }
+
+    pub fn is_active(&self, col: u32, row: u32) -> bool {
+        col < self.cols && row < self.rows && self.map[(row * self.cols + col) as usize] != 0
+    }
+
+    pub(super) fn validate(&self, cols: u32, rows: u32) -> Result<()> {
+        if self.cols != cols || self.rows != rows {
+            return Err(Error::Invalid(format!("active map is {}x{} blocks, encoder expects {}x{}", self.cols, self.rows, cols, rows).into()));
+        }
+
+        if self.map.len() != (cols * rows) as usize {
+            return Err(Error::Invalid(format!("active map has {} blocks, expected {}", self.map.len(), cols * rows).into()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active_blocks(active_map: &ActiveMap) -> Vec<(u32, u32)> {
+        (0..active_map.rows)
+            .flat_map(|row| (0..active_map.cols).map(move |col| (col, row)))
+            .filter(|(col, row)| active_map.is_active(*col, *row))
+            .collect()
+    }
+
+    #[test]
+    fn from_bitmap_finer_than_frame() {
+        // A 2x damage bitmap of a 64x64 frame, an isolated even cell covers
+        // half a pixel
+        let mut bitmap = vec![0; 128 * 128];
+        bitmap[32] = 1;
+
+        let active_map = ActiveMap::from_bitmap(64, 64, &bitmap, 128, 128).unwrap();
+
+        assert_eq!(active_blocks(&active_map), [(1, 0)]);
+    }
+
+    #[test]
+    fn from_bitmap_coarser_than_frame() {
+        let mut bitmap = vec![0; 2 * 2];
+        bitmap[1] = 1;
+
+        let active_map = ActiveMap::from_bitmap(64, 64, &bitmap, 2, 2).unwrap();
+
+        assert_eq!(active_blocks(&active_map), [(2, 0), (3, 0), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn from_bitmap_rejects_mismatched_size() {
+        assert!(ActiveMap::from_bitmap(64, 64, &[0; 4], u32::MAX, u32::MAX).is_err());
+    }
+}
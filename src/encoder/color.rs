@@ -0,0 +1,87 @@
+use std::os::raw::c_uint;
+
+use media_core::{
+    unsupported_error,
+    variant::Variant,
+    video::{ColorMatrix, ColorRange, VideoFrameDescriptor},
+    Result,
+};
+
+use crate::vpx_sys::{vpx_color_range, vpx_color_space};
+
+// sRGB is only coded in the 4:4:4 profiles 1 and 3, the others leave it
+// unspecified
+fn color_matrix_to_vpx_color_space(color_matrix: ColorMatrix, profile: c_uint) -> vpx_color_space {
+    use vpx_color_space::*;
+
+    match color_matrix {
+        ColorMatrix::BT470BG => VPX_CS_BT_601,
+        ColorMatrix::BT709 => VPX_CS_BT_709,
+        ColorMatrix::SMPTE170M => VPX_CS_SMPTE_170,
+        ColorMatrix::SMPTE240M => VPX_CS_SMPTE_240,
+        ColorMatrix::BT2020NCL | ColorMatrix::BT2020CL => VPX_CS_BT_2020,
+        ColorMatrix::Reserved => VPX_CS_RESERVED,
+        ColorMatrix::Identity if profile % 2 == 1 => VPX_CS_SRGB,
+        _ => VPX_CS_UNKNOWN,
+    }
+}
+
+fn color_range_to_vpx_color_range(color_range: ColorRange) -> vpx_color_range {
+    use vpx_color_range::*;
+
+    match color_range {
+        ColorRange::Full => VPX_CR_FULL_RANGE,
+        _ => VPX_CR_STUDIO_RANGE,
+    }
+}
+
+// Values left as None follow the descriptor of the input frames
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ColorSignalling {
+    pub color_matrix: Option<ColorMatrix>,
+    pub color_range: Option<ColorRange>,
+    pub render_size: Option<(u32, u32)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) struct VpxColorConfig {
+    pub color_space: vpx_color_space,
+    pub color_range: vpx_color_range,
+    pub render_size: (u32, u32),
+}
+
+impl ColorSignalling {
+    // Returns whether the key is a color option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "color_matrix" => {
+                if let Some(color_matrix) = value.get_uint64() {
+                    self.color_matrix = Some(ColorMatrix::try_from(color_matrix as usize)?);
+                }
+            }
+            "color_range" => {
+                if let Some(color_range) = value.get_uint64() {
+                    self.color_range = Some(ColorRange::from(color_range as usize));
+                }
+            }
+            // "WxH"
+            "render_size" => {
+                if let Some(render_size) = value.get_string() {
+                    let size = render_size.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+                    self.render_size = Some(size.ok_or_else(|| unsupported_error!(render_size))?);
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub(super) fn resolve(&self, desc: &VideoFrameDescriptor, profile: c_uint) -> VpxColorConfig {
+        VpxColorConfig {
+            color_space: color_matrix_to_vpx_color_space(self.color_matrix.unwrap_or(desc.color_matrix), profile),
+            color_range: color_range_to_vpx_color_range(self.color_range.unwrap_or(desc.color_range)),
+            render_size: self.render_size.unwrap_or((desc.width().get(), desc.height().get())),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{mem, ptr};
+
+use media_core::{error::Error, Result};
+
+use crate::vpx_sys::vpx_rc_frame_stats;
+
+// VP9 first pass statistics of a frame, or of the whole sequence for the
+// totals
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FirstPassStats {
+    // Frame index, the frame count for the totals
+    pub frame: f64,
+    pub weight: f64,
+    pub intra_error: f64,
+    pub coded_error: f64,
+    // Error when predicting from the second reference
+    pub sr_coded_error: f64,
+    pub frame_noise_energy: f64,
+    // Fractions of blocks, 0 to 1
+    pub pcnt_inter: f64,
+    pub pcnt_motion: f64,
+    pub pcnt_second_ref: f64,
+    pub pcnt_neutral: f64,
+    pub pcnt_intra_low: f64,
+    pub pcnt_intra_high: f64,
+    pub intra_skip_pct: f64,
+    pub intra_smooth_pct: f64,
+    pub inactive_zone_rows: f64,
+    pub inactive_zone_cols: f64,
+    // Motion vector sums over the inter blocks
+    pub mv_row: f64,
+    pub mv_row_abs: f64,
+    pub mv_col: f64,
+    pub mv_col_abs: f64,
+    pub mv_row_variance: f64,
+    pub mv_col_variance: f64,
+    pub mv_in_out_count: f64,
+    pub duration: f64,
+    pub count: f64,
+    pub new_mv_count: f64,
+}
+
+impl From<&vpx_rc_frame_stats> for FirstPassStats {
+    fn from(stats: &vpx_rc_frame_stats) -> Self {
+        Self {
+            frame: stats.frame,
+            weight: stats.weight,
+            intra_error: stats.intra_error,
+            coded_error: stats.coded_error,
+            sr_coded_error: stats.sr_coded_error,
+            frame_noise_energy: stats.frame_noise_energy,
+            pcnt_inter: stats.pcnt_inter,
+            pcnt_motion: stats.pcnt_motion,
+            pcnt_second_ref: stats.pcnt_second_ref,
+            pcnt_neutral: stats.pcnt_neutral,
+            pcnt_intra_low: stats.pcnt_intra_low,
+            pcnt_intra_high: stats.pcnt_intra_high,
+            intra_skip_pct: stats.intra_skip_pct,
+            intra_smooth_pct: stats.intra_smooth_pct,
+            inactive_zone_rows: stats.inactive_zone_rows,
+            inactive_zone_cols: stats.inactive_zone_cols,
+            mv_row: stats.MVr,
+            mv_row_abs: stats.mvr_abs,
+            mv_col: stats.MVc,
+            mv_col_abs: stats.mvc_abs,
+            mv_row_variance: stats.MVrv,
+            mv_col_variance: stats.MVcv,
+            mv_in_out_count: stats.mv_in_out_count,
+            duration: stats.duration,
+            count: stats.count,
+            new_mv_count: stats.new_mv_count,
+        }
+    }
+}
+
+impl FirstPassStats {
+    // The VP9 stats packets hold the internal first pass struct, which starts
+    // with the fields of the C struct the ext rate control mirrors and may
+    // append more, VP8 uses a different layout
+    pub(super) fn from_packet(data: &[u8]) -> Result<Self> {
+        if data.len() < mem::size_of::<vpx_rc_frame_stats>() {
+            return Err(Error::Invalid(format!("first pass stats packet of {} bytes is too short", data.len()).into()));
+        }
+
+        let stats = unsafe { ptr::read_unaligned(data.as_ptr() as *const vpx_rc_frame_stats) };
+
+        Ok(Self::from(&stats))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FirstPass {
+    // Stats of each frame, VP9 only
+    pub frames: Vec<FirstPassStats>,
+    pub total: Option<FirstPassStats>,
+    // Raw stats to pass as the two_pass_stats option of the last pass
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The libvpx struct appends the spatial layer id to the documented fields
+    const PACKET_SIZE: usize = mem::size_of::<vpx_rc_frame_stats>() + mem::size_of::<i64>();
+
+    #[test]
+    fn from_packet_reads_documented_fields() {
+        let mut data = Vec::with_capacity(PACKET_SIZE);
+        for field in 0..mem::size_of::<vpx_rc_frame_stats>() / mem::size_of::<f64>() {
+            data.extend_from_slice(&(field as f64 + 1.0).to_ne_bytes());
+        }
+        data.extend_from_slice(&2i64.to_ne_bytes());
+
+        let stats = FirstPassStats::from_packet(&data).unwrap();
+
+        assert_ne!(stats, FirstPassStats::default());
+        assert_eq!(stats.frame, 1.0);
+        assert_eq!(stats.coded_error, 4.0);
+        assert_eq!(stats.new_mv_count, 26.0);
+    }
+
+    #[test]
+    fn from_packet_rejects_short_packets() {
+        assert!(FirstPassStats::from_packet(&[0; 16]).is_err());
+    }
+}
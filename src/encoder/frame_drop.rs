@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+use media_core::{error::Error, unsupported_error, variant::Variant, Result};
+
+use crate::vpx_sys::SVC_LAYER_DROP_MODE;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LayerDropMode {
+    // Dropping a spatial layer also drops the layers above
+    Constrained,
+    // Drop any spatial layer on its own
+    Layer,
+    // Drop whole superframes only
+    FullSuperframe,
+    // Dropping a spatial layer also drops the layers below
+    ConstrainedFromAbove,
+}
+
+impl FromStr for LayerDropMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "constrained" => Ok(LayerDropMode::Constrained),
+            "layer" => Ok(LayerDropMode::Layer),
+            "full_superframe" => Ok(LayerDropMode::FullSuperframe),
+            "constrained_from_above" => Ok(LayerDropMode::ConstrainedFromAbove),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+impl From<LayerDropMode> for SVC_LAYER_DROP_MODE {
+    fn from(mode: LayerDropMode) -> Self {
+        match mode {
+            LayerDropMode::Constrained => SVC_LAYER_DROP_MODE::CONSTRAINED_LAYER_DROP,
+            LayerDropMode::Layer => SVC_LAYER_DROP_MODE::LAYER_DROP,
+            LayerDropMode::FullSuperframe => SVC_LAYER_DROP_MODE::FULL_SUPERFRAME_DROP,
+            LayerDropMode::ConstrainedFromAbove => SVC_LAYER_DROP_MODE::CONSTRAINED_FROM_ABOVE_DROP,
+        }
+    }
+}
+
+// Controls left as None keep the encoder defaults, all but threshold are VP9
+// only and the layer drop settings apply to SVC encoding
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FrameDropOptions {
+    // Buffer level in percent below which frames are dropped, 0 disables
+    // dropping
+    pub threshold: Option<u32>,
+    pub postencode_drop: Option<bool>,
+    pub disable_overshoot_maxq_cbr: Option<bool>,
+    pub layer_drop_mode: Option<LayerDropMode>,
+    pub max_consecutive_drops: Option<i32>,
+}
+
+impl FrameDropOptions {
+    // Returns whether the key is a frame drop option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "drop_frame_threshold" => self.threshold = value.get_uint32().or(self.threshold),
+            "postencode_drop" => self.postencode_drop = value.get_bool().or(self.postencode_drop),
+            "disable_overshoot_maxq_cbr" => self.disable_overshoot_maxq_cbr = value.get_bool().or(self.disable_overshoot_maxq_cbr),
+            "layer_drop_mode" => {
+                if let Some(mode) = value.get_string() {
+                    self.layer_drop_mode = Some(mode.parse()?);
+                }
+            }
+            "max_consecutive_drops" => self.max_consecutive_drops = value.get_int32().or(self.max_consecutive_drops),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub(super) fn merge(&mut self, other: &FrameDropOptions) {
+        self.threshold = other.threshold.or(self.threshold);
+        self.postencode_drop = other.postencode_drop.or(self.postencode_drop);
+        self.disable_overshoot_maxq_cbr = other.disable_overshoot_maxq_cbr.or(self.disable_overshoot_maxq_cbr);
+        self.layer_drop_mode = other.layer_drop_mode.or(self.layer_drop_mode);
+        self.max_consecutive_drops = other.max_consecutive_drops.or(self.max_consecutive_drops);
+    }
+}
@@ -0,0 +1,83 @@
+use media_core::{error::Error, unsupported_error, variant::Variant, Result};
+
+// VP9E_SET_TARGET_LEVEL value that keeps the level statistics without
+// constraining the encoder
+pub(super) const LEVEL_STATS_ONLY: u32 = 0;
+
+struct LevelSpec {
+    level: u32,
+    max_luma_sample_rate: u64,
+    max_luma_picture_size: u32,
+    max_luma_picture_breadth: u32,
+    // In kbps
+    average_bitrate: u32,
+}
+
+const fn spec(level: u32, max_luma_sample_rate: u64, max_luma_picture_size: u32, max_luma_picture_breadth: u32, average_bitrate: u32) -> LevelSpec {
+    LevelSpec {
+        level,
+        max_luma_sample_rate,
+        max_luma_picture_size,
+        max_luma_picture_breadth,
+        average_bitrate,
+    }
+}
+
+// Limits of the VP9 levels as enforced by libvpx
+const LEVEL_SPECS: [LevelSpec; 14] = [
+    spec(10, 829440, 36864, 512, 200),
+    spec(11, 2764800, 73728, 768, 800),
+    spec(20, 4608000, 122880, 960, 1800),
+    spec(21, 9216000, 245760, 1344, 3600),
+    spec(30, 20736000, 552960, 2048, 7200),
+    spec(31, 36864000, 983040, 2752, 12000),
+    spec(40, 83558400, 2228224, 4160, 18000),
+    spec(41, 160432128, 2228224, 4160, 30000),
+    spec(50, 311951360, 8912896, 8384, 60000),
+    spec(51, 588251136, 8912896, 8384, 120000),
+    spec(52, 1176502272, 8912896, 8384, 180000),
+    spec(60, 1176502272, 35651584, 16832, 180000),
+    spec(61, 2353004544, 35651584, 16832, 240000),
+    spec(62, 4706009088, 35651584, 16832, 480000),
+];
+
+// Accept a level as a number such as 41 or a string such as "4.1"
+pub(super) fn parse_level(value: &Variant) -> Result<Option<u32>> {
+    if let Some(level) = value.get_string() {
+        let (major, minor) = level.split_once('.').unwrap_or((level.as_str(), "0"));
+
+        return match (major.parse::<u32>(), minor.parse::<u32>()) {
+            (Ok(major), Ok(minor)) if minor < 10 => Ok(Some(major * 10 + minor)),
+            _ => Err(unsupported_error!(level)),
+        };
+    }
+
+    Ok(value.get_uint32())
+}
+
+// Check that the stream fits the level, the frame rate and bitrate are only
+// checked when known
+pub(super) fn validate_level(level: u32, width: u32, height: u32, frame_rate: Option<f64>, bit_rate: Option<u32>) -> Result<()> {
+    let spec = LEVEL_SPECS.iter().find(|spec| spec.level == level).ok_or_else(|| unsupported_error!(level))?;
+    let picture_size = width as u64 * height as u64;
+
+    if picture_size > spec.max_luma_picture_size as u64 || width.max(height) > spec.max_luma_picture_breadth {
+        return Err(Error::Invalid(format!("{}x{} exceeds the picture size of level {}.{}", width, height, level / 10, level % 10).into()));
+    }
+
+    if let Some(frame_rate) = frame_rate {
+        if picture_size as f64 * frame_rate > spec.max_luma_sample_rate as f64 {
+            return Err(Error::Invalid(
+                format!("{}x{} at {} fps exceeds the sample rate of level {}.{}", width, height, frame_rate, level / 10, level % 10).into(),
+            ));
+        }
+    }
+
+    if let Some(bit_rate) = bit_rate {
+        if bit_rate > spec.average_bitrate {
+            return Err(Error::Invalid(format!("{} kbps exceeds the bitrate of level {}.{}", bit_rate, level / 10, level % 10).into()));
+        }
+    }
+
+    Ok(())
+}
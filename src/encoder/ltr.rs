@@ -0,0 +1,220 @@
+use std::os::raw::c_int;
+
+use crate::vpx_sys::{
+    vpx_codec_enc_cfg_t, vpx_svc_extra_cfg_t, vpx_svc_ref_frame_config_t, VP8_EFLAG_FORCE_ARF, VP8_EFLAG_FORCE_GF, VP8_EFLAG_NO_REF_ARF,
+    VP8_EFLAG_NO_REF_GF, VP8_EFLAG_NO_REF_LAST, VP8_EFLAG_NO_UPD_ARF, VP8_EFLAG_NO_UPD_GF, VP9E_TEMPORAL_LAYERING_MODE, VPX_EFLAG_FORCE_KF,
+};
+
+// VP9 buffer slots of the last frame and of the two long-term references
+const VP9_LAST_SLOT: c_int = 0;
+const VP9_LTR_SLOTS: [c_int; 2] = [1, 2];
+
+// Long-term reference held by a slot
+#[derive(Clone, Copy, Debug)]
+struct Reference {
+    pts: i64,
+    acknowledged: bool,
+}
+
+// References of the next frame, the last frame is always updated
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct ReferenceDecision {
+    pub key_frame: bool,
+    pub reference_last: bool,
+    pub reference: [bool; 2],
+    pub update: [bool; 2],
+}
+
+impl ReferenceDecision {
+    pub fn updates_long_term_reference(&self) -> bool {
+        self.key_frame || self.update.iter().any(|update| *update)
+    }
+
+    pub fn vp8_flags(&self) -> u32 {
+        if self.key_frame {
+            return VPX_EFLAG_FORCE_KF;
+        }
+
+        let mut flags = 0;
+
+        if !self.reference_last {
+            flags |= VP8_EFLAG_NO_REF_LAST;
+        }
+        if !self.reference[0] {
+            flags |= VP8_EFLAG_NO_REF_GF;
+        }
+        if !self.reference[1] {
+            flags |= VP8_EFLAG_NO_REF_ARF;
+        }
+
+        flags |= if self.update[0] {
+            VP8_EFLAG_FORCE_GF
+        } else {
+            VP8_EFLAG_NO_UPD_GF
+        };
+        flags |= if self.update[1] {
+            VP8_EFLAG_FORCE_ARF
+        } else {
+            VP8_EFLAG_NO_UPD_ARF
+        };
+
+        flags
+    }
+
+    // Golden and alt-ref point to the two long-term reference slots
+    pub fn vp9_ref_frame_config(&self) -> vpx_svc_ref_frame_config_t {
+        let mut config = vpx_svc_ref_frame_config_t {
+            lst_fb_idx: [VP9_LAST_SLOT; 5],
+            gld_fb_idx: [VP9_LTR_SLOTS[0]; 5],
+            alt_fb_idx: [VP9_LTR_SLOTS[1]; 5],
+            update_buffer_slot: [0; 5],
+            update_last: [0; 5],
+            update_golden: [0; 5],
+            update_alt_ref: [0; 5],
+            reference_last: [0; 5],
+            reference_golden: [0; 5],
+            reference_alt_ref: [0; 5],
+            duration: [0; 5],
+        };
+
+        config.update_buffer_slot[0] = 1 << VP9_LAST_SLOT;
+
+        if self.key_frame {
+            return config;
+        }
+
+        config.reference_last[0] = self.reference_last as c_int;
+        config.reference_golden[0] = self.reference[0] as c_int;
+        config.reference_alt_ref[0] = self.reference[1] as c_int;
+
+        for (slot, update) in VP9_LTR_SLOTS.iter().zip(self.update) {
+            if update {
+                config.update_buffer_slot[0] |= 1 << slot;
+            }
+        }
+
+        config
+    }
+}
+
+// Two buffers alternate as long-term references, so that marking a new one
+// keeps the acknowledged one until the receiver confirms the new one
+#[derive(Debug, Default)]
+pub(super) struct LongTermReferences {
+    slots: [Option<Reference>; 2],
+    mark_requested: bool,
+    recovery_requested: bool,
+}
+
+impl LongTermReferences {
+    pub fn mark(&mut self) {
+        self.mark_requested = true;
+    }
+
+    pub fn request_recovery(&mut self) {
+        self.recovery_requested = true;
+    }
+
+    pub fn acknowledge(&mut self, pts: i64) {
+        for reference in self.slots.iter_mut().flatten() {
+            if reference.pts == pts {
+                reference.acknowledged = true;
+            }
+        }
+    }
+
+    // Slot of the most recent acknowledged reference
+    fn acknowledged_slot(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, reference)| reference.filter(|reference| reference.acknowledged).map(|reference| (slot, reference.pts)))
+            .max_by_key(|(_, pts)| *pts)
+            .map(|(slot, _)| slot)
+    }
+
+    pub fn decide(&mut self, pts: i64) -> ReferenceDecision {
+        let mut decision = ReferenceDecision {
+            reference_last: true,
+            reference: [self.slots[0].is_some(), self.slots[1].is_some()],
+            ..Default::default()
+        };
+
+        if self.recovery_requested {
+            self.recovery_requested = false;
+
+            // Without an acknowledged reference only a keyframe recovers
+            match self.acknowledged_slot() {
+                Some(slot) => {
+                    decision.reference_last = false;
+                    decision.reference = [false; 2];
+                    decision.reference[slot] = true;
+                }
+                None => {
+                    decision.key_frame = true;
+                    self.key_frame(pts);
+                    return decision;
+                }
+            }
+        }
+
+        if self.mark_requested {
+            self.mark_requested = false;
+
+            // Replace the slot not holding the acknowledged reference
+            let slot = match self.acknowledged_slot() {
+                Some(slot) => 1 - slot,
+                None => self.slots.iter().position(|reference| reference.is_none()).unwrap_or(0),
+            };
+
+            decision.update[slot] = true;
+            self.slots[slot] = Some(Reference {
+                pts,
+                acknowledged: false,
+            });
+        }
+
+        decision
+    }
+
+    // Keyframes refresh every buffer
+    pub fn key_frame(&mut self, pts: i64) {
+        let reference = Reference {
+            pts,
+            acknowledged: false,
+        };
+
+        self.slots = [Some(reference); 2];
+    }
+}
+
+// Single layer SVC mode, which lets VP9 take the buffer slots of each frame
+pub(super) fn configure_vp9(cfg: &mut vpx_codec_enc_cfg_t) {
+    cfg.g_lag_in_frames = 0;
+    cfg.ss_number_layers = 1;
+    cfg.ts_number_layers = 1;
+    cfg.temporal_layering_mode = VP9E_TEMPORAL_LAYERING_MODE::VP9E_TEMPORAL_LAYERING_MODE_BYPASS as c_int;
+    configure_vp9_bitrate(cfg);
+}
+
+pub(super) fn configure_vp9_bitrate(cfg: &mut vpx_codec_enc_cfg_t) {
+    cfg.ss_target_bitrate[0] = cfg.rc_target_bitrate;
+    cfg.layer_target_bitrate[0] = cfg.rc_target_bitrate;
+}
+
+pub(super) fn vp9_svc_parameters(cfg: &vpx_codec_enc_cfg_t) -> vpx_svc_extra_cfg_t {
+    let mut params = vpx_svc_extra_cfg_t {
+        max_quantizers: [0; 12],
+        min_quantizers: [0; 12],
+        scaling_factor_num: [1; 12],
+        scaling_factor_den: [1; 12],
+        speed_per_layer: [0; 12],
+        temporal_layering_mode: VP9E_TEMPORAL_LAYERING_MODE::VP9E_TEMPORAL_LAYERING_MODE_BYPASS as c_int,
+        loopfilter_ctrl: [0; 12],
+    };
+
+    params.max_quantizers[0] = cfg.rc_max_quantizer as c_int;
+    params.min_quantizers[0] = cfg.rc_min_quantizer as c_int;
+
+    params
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use media_core::{error::Error, unsupported_error, variant::Variant, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AqMode {
+    None          = 0,
+    Variance      = 1,
+    Complexity    = 2,
+    CyclicRefresh = 3,
+}
+
+impl FromStr for AqMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(AqMode::None),
+            "variance" => Ok(AqMode::Variance),
+            "complexity" => Ok(AqMode::Complexity),
+            "cyclic_refresh" => Ok(AqMode::CyclicRefresh),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArnrType {
+    Backward = 1,
+    Forward  = 2,
+    Centered = 3,
+}
+
+impl FromStr for ArnrType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "backward" => Ok(ArnrType::Backward),
+            "forward" => Ok(ArnrType::Forward),
+            "centered" => Ok(ArnrType::Centered),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+// Controls left as None keep the encoder defaults, all but auto_alt_ref and the
+// ARNR filter are VP9 only
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QualityOptions {
+    pub aq_mode: Option<AqMode>,
+    pub alt_ref_aq: Option<bool>,
+    // 0 or 1 for VP8, number of alt-ref frames per group up to 6 for VP9
+    pub auto_alt_ref: Option<u32>,
+    // 0 to 15
+    pub arnr_max_frames: Option<u32>,
+    // 0 to 6
+    pub arnr_strength: Option<u32>,
+    pub arnr_type: Option<ArnrType>,
+    pub min_gf_interval: Option<u32>,
+    pub max_gf_interval: Option<u32>,
+    pub frame_periodic_boost: Option<bool>,
+    // -15 to 15
+    pub delta_q_uv: Option<i32>,
+}
+
+impl QualityOptions {
+    // Returns whether the key is a quality option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "aq_mode" => {
+                if let Some(aq_mode) = value.get_string() {
+                    self.aq_mode = Some(aq_mode.parse()?);
+                }
+            }
+            "alt_ref_aq" => self.alt_ref_aq = value.get_bool().or(self.alt_ref_aq),
+            "auto_alt_ref" => self.auto_alt_ref = value.get_uint32().or(self.auto_alt_ref),
+            "arnr_max_frames" => self.arnr_max_frames = value.get_uint32().or(self.arnr_max_frames),
+            "arnr_strength" => self.arnr_strength = value.get_uint32().or(self.arnr_strength),
+            "arnr_type" => {
+                if let Some(arnr_type) = value.get_string() {
+                    self.arnr_type = Some(arnr_type.parse()?);
+                }
+            }
+            "min_gf_interval" => self.min_gf_interval = value.get_uint32().or(self.min_gf_interval),
+            "max_gf_interval" => self.max_gf_interval = value.get_uint32().or(self.max_gf_interval),
+            "frame_periodic_boost" => self.frame_periodic_boost = value.get_bool().or(self.frame_periodic_boost),
+            "delta_q_uv" => self.delta_q_uv = value.get_int32().or(self.delta_q_uv),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use media_core::{error::Error, unsupported_error, Result};
+
+use super::{FirstPassStats, ReferenceFrame, TplGopStats};
+use crate::vpx_sys::{
+    vpx_ext_rc_mode, vpx_rc_config_t, vpx_rc_encodeframe_decision_t, vpx_rc_encodeframe_info_t, vpx_rc_encodeframe_result_t,
+    vpx_rc_firstpass_stats_t, vpx_rc_frame_update_type, vpx_rc_funcs_t, vpx_rc_gop_decision_t, vpx_rc_key_frame_decision_t, vpx_rc_model_t,
+    vpx_rc_ref_name, vpx_rc_status_t, vpx_rc_type, VpxTplGopStats,
+};
+
+// Maximum number of coding frames of a GOP decision
+const MAX_GOP_FRAMES: usize = 252;
+// Maximum number of references of each frame of a GOP decision
+const MAX_GOP_REFERENCES: usize = 4;
+
+// Decisions taken by the rate controller, the others are left to libvpx
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateControlType {
+    // Only receives the stats
+    None,
+    Qp,
+    Gop,
+    Rdmult,
+    GopQp,
+    GopQpRdmult,
+}
+
+impl From<RateControlType> for vpx_rc_type {
+    fn from(rc_type: RateControlType) -> Self {
+        match rc_type {
+            RateControlType::None => vpx_rc_type::VPX_RC_NONE,
+            RateControlType::Qp => vpx_rc_type::VPX_RC_QP,
+            RateControlType::Gop => vpx_rc_type::VPX_RC_GOP,
+            RateControlType::Rdmult => vpx_rc_type::VPX_RC_RDMULT,
+            RateControlType::GopQp => vpx_rc_type::VPX_RC_GOP_QP,
+            RateControlType::GopQpRdmult => vpx_rc_type::VPX_RC_GOP_QP_RDMULT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateControlMode {
+    Q,
+    Vbr,
+    Cq,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateControlConfig {
+    pub width: u32,
+    pub height: u32,
+    // Number of frames of the first pass stats
+    pub frame_count: u32,
+    pub min_gf_interval: u32,
+    pub max_gf_interval: u32,
+    // In kbps
+    pub target_bitrate: u32,
+    pub frame_rate: (i32, i32),
+    pub mode: RateControlMode,
+    pub overshoot_percent: u32,
+    pub undershoot_percent: u32,
+    // Quantizer indexes on the 0 to 255 scale
+    pub min_base_q_index: i32,
+    pub max_base_q_index: i32,
+    pub base_qp: i32,
+}
+
+impl From<&vpx_rc_config_t> for RateControlConfig {
+    fn from(config: &vpx_rc_config_t) -> Self {
+        Self {
+            width: config.frame_width as u32,
+            height: config.frame_height as u32,
+            frame_count: config.show_frame_count as u32,
+            min_gf_interval: config.min_gf_interval as u32,
+            max_gf_interval: config.max_gf_interval as u32,
+            target_bitrate: config.target_bitrate_kbps as u32,
+            frame_rate: (config.frame_rate_num, config.frame_rate_den),
+            mode: match config.rc_mode {
+                vpx_ext_rc_mode::VPX_RC_QMODE => RateControlMode::Q,
+                vpx_ext_rc_mode::VPX_RC_VBR => RateControlMode::Vbr,
+                vpx_ext_rc_mode::VPX_RC_CQ => RateControlMode::Cq,
+            },
+            overshoot_percent: config.overshoot_percent as u32,
+            undershoot_percent: config.undershoot_percent as u32,
+            min_base_q_index: config.min_base_q_index,
+            max_base_q_index: config.max_base_q_index,
+            base_qp: config.base_qp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameDecision {
+    // 0 to 255
+    pub q_index: i32,
+    pub rdmult: i32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameResult {
+    pub bit_count: i64,
+    pub q_index: i32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameInfo {
+    pub key_frame: bool,
+    pub show_index: i32,
+    pub coding_index: i32,
+    pub gop_index: i32,
+    pub gop_size: i32,
+    pub use_alt_ref: bool,
+    // Coding indexes of the last, golden and alt-ref references, None when the
+    // reference is not valid
+    pub ref_frame_coding_indexes: [Option<i32>; 3],
+}
+
+impl From<&vpx_rc_encodeframe_info_t> for FrameInfo {
+    fn from(info: &vpx_rc_encodeframe_info_t) -> Self {
+        Self {
+            key_frame: info.frame_type == 0,
+            show_index: info.show_index,
+            coding_index: info.coding_index,
+            gop_index: info.gop_index,
+            gop_size: info.gop_size,
+            use_alt_ref: info.use_alt_ref != 0,
+            ref_frame_coding_indexes: std::array::from_fn(|i| (info.ref_frame_valid_list[i] != 0).then_some(info.ref_frame_coding_indexes[i])),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameUpdateType {
+    KeyFrame,
+    LastFrame,
+    GoldenFrame,
+    AltRef,
+    Overlay,
+    MidOverlay,
+    UseBufferedFrame,
+}
+
+impl From<FrameUpdateType> for vpx_rc_frame_update_type {
+    fn from(update_type: FrameUpdateType) -> Self {
+        match update_type {
+            FrameUpdateType::KeyFrame => vpx_rc_frame_update_type::VPX_RC_KF_UPDATE,
+            FrameUpdateType::LastFrame => vpx_rc_frame_update_type::VPX_RC_LF_UPDATE,
+            FrameUpdateType::GoldenFrame => vpx_rc_frame_update_type::VPX_RC_GF_UPDATE,
+            FrameUpdateType::AltRef => vpx_rc_frame_update_type::VPX_RC_ARF_UPDATE,
+            FrameUpdateType::Overlay => vpx_rc_frame_update_type::VPX_RC_OVERLAY_UPDATE,
+            FrameUpdateType::MidOverlay => vpx_rc_frame_update_type::VPX_RC_MID_OVERLAY_UPDATE,
+            FrameUpdateType::UseBufferedFrame => vpx_rc_frame_update_type::VPX_RC_USE_BUF_FRAME,
+        }
+    }
+}
+
+fn ref_name(reference: ReferenceFrame) -> vpx_rc_ref_name {
+    match reference {
+        ReferenceFrame::Intra => vpx_rc_ref_name::VPX_RC_INTRA_FRAME,
+        ReferenceFrame::Last => vpx_rc_ref_name::VPX_RC_LAST_FRAME,
+        ReferenceFrame::Golden => vpx_rc_ref_name::VPX_RC_GOLDEN_FRAME,
+        ReferenceFrame::AltRef => vpx_rc_ref_name::VPX_RC_ALTREF_FRAME,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GopFrame {
+    pub update_type: FrameUpdateType,
+    // Reference buffer refreshed by the frame
+    pub update_ref_index: i32,
+    // Up to 4 references with the buffer index they are read from
+    pub references: Vec<(ReferenceFrame, i32)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GopDecision {
+    pub use_alt_ref: bool,
+    pub use_key_frame: bool,
+    // Frames of the group in coding order
+    pub frames: Vec<GopFrame>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyFrameDecision {
+    pub show_index: i32,
+    pub group_size: i32,
+}
+
+// External VP9 rate control, only the decisions of the rate control type are
+// requested by the encoder
+pub trait RateController: Send {
+    fn rate_control_type(&self) -> RateControlType;
+
+    fn configure(&mut self, _config: &RateControlConfig) -> Result<()> {
+        Ok(())
+    }
+
+    fn frame_decision(&mut self, _gop_index: i32) -> Result<FrameDecision> {
+        Err(unsupported_error!("frame_decision"))
+    }
+
+    fn frame_result(&mut self, _result: &FrameResult) -> Result<()> {
+        Ok(())
+    }
+
+    fn frame_rdmult(&mut self, _info: &FrameInfo) -> Result<i32> {
+        Err(unsupported_error!("frame_rdmult"))
+    }
+
+    fn key_frame_decision(&mut self) -> Result<KeyFrameDecision> {
+        Err(unsupported_error!("key_frame_decision"))
+    }
+
+    fn gop_decision(&mut self) -> Result<GopDecision> {
+        Err(unsupported_error!("gop_decision"))
+    }
+
+    // Stats of the first pass, sent when the controller is set
+    fn first_pass_stats(&mut self, _stats: &[FirstPassStats]) -> Result<()> {
+        Ok(())
+    }
+
+    // Sent before the frames of each GOP when the encoder has the tpl option
+    fn tpl_gop_stats(&mut self, _stats: TplGopStats) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Box the trait object again so that libvpx gets a thin pointer to it
+pub(super) type RateControllerBox = Box<Box<dyn RateController>>;
+
+pub(super) fn rate_control_funcs(controller: &mut RateControllerBox) -> vpx_rc_funcs_t {
+    vpx_rc_funcs_t {
+        rc_type: controller.rate_control_type().into(),
+        create_model: Some(create_model),
+        send_firstpass_stats: Some(send_firstpass_stats),
+        send_tpl_gop_stats: Some(send_tpl_gop_stats),
+        get_encodeframe_decision: Some(get_encodeframe_decision),
+        update_encodeframe_result: Some(update_encodeframe_result),
+        get_key_frame_decision: Some(get_key_frame_decision),
+        get_gop_decision: Some(get_gop_decision),
+        get_frame_rdmult: Some(get_frame_rdmult),
+        delete_model: Some(delete_model),
+        rate_ctrl_log_path: ptr::null(),
+        priv_: controller.as_mut() as *mut Box<dyn RateController> as *mut _,
+    }
+}
+
+unsafe fn controller<'a>(model: vpx_rc_model_t) -> &'a mut dyn RateController {
+    (*(model as *mut Box<dyn RateController>)).as_mut()
+}
+
+// A panic must not unwind into libvpx, it fails the call like an error
+fn status(f: impl FnOnce() -> Result<()>) -> vpx_rc_status_t {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => vpx_rc_status_t::VPX_RC_OK,
+        Ok(Err(_)) | Err(_) => vpx_rc_status_t::VPX_RC_ERROR,
+    }
+}
+
+unsafe extern "C" fn create_model(priv_: *mut std::os::raw::c_void, config: *const vpx_rc_config_t, model: *mut vpx_rc_model_t) -> vpx_rc_status_t {
+    *model = priv_;
+
+    status(|| controller(priv_).configure(&RateControlConfig::from(&*config)))
+}
+
+unsafe extern "C" fn send_firstpass_stats(model: vpx_rc_model_t, stats: *const vpx_rc_firstpass_stats_t) -> vpx_rc_status_t {
+    status(|| {
+        let stats = &*stats;
+        let stats: Vec<_> = if stats.frame_stats.is_null() || stats.num_frames <= 0 {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(stats.frame_stats, stats.num_frames as usize).iter().map(FirstPassStats::from).collect()
+        };
+
+        controller(model).first_pass_stats(&stats)
+    })
+}
+
+unsafe extern "C" fn send_tpl_gop_stats(model: vpx_rc_model_t, stats: *const VpxTplGopStats) -> vpx_rc_status_t {
+    status(|| controller(model).tpl_gop_stats(TplGopStats::from(&*stats)))
+}
+
+unsafe extern "C" fn get_encodeframe_decision(
+    model: vpx_rc_model_t,
+    gop_index: c_int,
+    decision: *mut vpx_rc_encodeframe_decision_t,
+) -> vpx_rc_status_t {
+    status(|| {
+        let frame_decision = controller(model).frame_decision(gop_index)?;
+        (*decision).q_index = frame_decision.q_index;
+        (*decision).rdmult = frame_decision.rdmult;
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn update_encodeframe_result(model: vpx_rc_model_t, result: *const vpx_rc_encodeframe_result_t) -> vpx_rc_status_t {
+    let result = FrameResult {
+        bit_count: (*result).bit_count,
+        q_index: (*result).actual_encoding_qindex,
+    };
+
+    status(|| controller(model).frame_result(&result))
+}
+
+unsafe extern "C" fn get_key_frame_decision(model: vpx_rc_model_t, decision: *mut vpx_rc_key_frame_decision_t) -> vpx_rc_status_t {
+    status(|| {
+        let key_frame_decision = controller(model).key_frame_decision()?;
+        (*decision).key_frame_show_index = key_frame_decision.show_index;
+        (*decision).key_frame_group_size = key_frame_decision.group_size;
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn get_gop_decision(model: vpx_rc_model_t, decision: *mut vpx_rc_gop_decision_t) -> vpx_rc_status_t {
+    status(|| {
+        let gop_decision = controller(model).gop_decision()?;
+        let decision = &mut *decision;
+
+        if gop_decision.frames.len() > MAX_GOP_FRAMES {
+            return Err(Error::Invalid(format!("GOP of {} frames exceeds {}", gop_decision.frames.len(), MAX_GOP_FRAMES).into()));
+        }
+
+        decision.gop_coding_frames = gop_decision.frames.len() as c_int;
+        decision.use_alt_ref = gop_decision.use_alt_ref as c_int;
+        decision.use_key_frame = gop_decision.use_key_frame as c_int;
+
+        for (i, frame) in gop_decision.frames.iter().enumerate() {
+            if frame.references.len() > MAX_GOP_REFERENCES {
+                return Err(Error::Invalid(format!("{} references exceed {}", frame.references.len(), MAX_GOP_REFERENCES).into()));
+            }
+
+            decision.update_type[i] = frame.update_type.into();
+            decision.update_ref_index[i] = frame.update_ref_index;
+
+            let ref_frame = &mut decision.ref_frame_list[i];
+            ref_frame.index = [-1; MAX_GOP_REFERENCES];
+            ref_frame.name = [vpx_rc_ref_name::VPX_RC_INVALID_REF_FRAME; MAX_GOP_REFERENCES];
+
+            for (j, (reference, index)) in frame.references.iter().enumerate() {
+                ref_frame.index[j] = *index;
+                ref_frame.name[j] = ref_name(*reference);
+            }
+        }
+
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn get_frame_rdmult(model: vpx_rc_model_t, info: *const vpx_rc_encodeframe_info_t, rdmult: *mut c_int) -> vpx_rc_status_t {
+    status(|| {
+        *rdmult = controller(model).frame_rdmult(&FrameInfo::from(&*info))?;
+        Ok(())
+    })
+}
+
+// The controller is owned by the encoder
+unsafe extern "C" fn delete_model(_model: vpx_rc_model_t) -> vpx_rc_status_t {
+    vpx_rc_status_t::VPX_RC_OK
+}
@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+use media_core::{error::Error, unsupported_error, variant::Variant, Result};
+
+use crate::vpx_sys::{vpx_codec_enc_cfg_t, VPX_SCALING_MODE};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ScalingMode {
+    #[default]
+    Normal,
+    FourFifths,
+    ThreeFifths,
+    OneHalf,
+}
+
+impl FromStr for ScalingMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "normal" | "1/1" => Ok(ScalingMode::Normal),
+            "4/5" => Ok(ScalingMode::FourFifths),
+            "3/5" => Ok(ScalingMode::ThreeFifths),
+            "1/2" => Ok(ScalingMode::OneHalf),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+impl From<ScalingMode> for VPX_SCALING_MODE {
+    fn from(mode: ScalingMode) -> Self {
+        match mode {
+            ScalingMode::Normal => VPX_SCALING_MODE::VP8E_NORMAL,
+            ScalingMode::FourFifths => VPX_SCALING_MODE::VP8E_FOURFIVE,
+            ScalingMode::ThreeFifths => VPX_SCALING_MODE::VP8E_THREEFIVE,
+            ScalingMode::OneHalf => VPX_SCALING_MODE::VP8E_ONETWO,
+        }
+    }
+}
+
+// Values left as None keep the encoder defaults
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResizeOptions {
+    // Let the rate control scale the frames down under bitrate pressure
+    pub resize_allowed: Option<bool>,
+    // Buffer levels in percent to scale up and down at, VP8 only
+    pub up_threshold: Option<u32>,
+    pub down_threshold: Option<u32>,
+    // Fixed coded size when resizing is allowed, VP9 only
+    pub scaled_size: Option<(u32, u32)>,
+    // Explicit ratio of both dimensions
+    pub scaling_mode: Option<ScalingMode>,
+}
+
+impl ResizeOptions {
+    // Returns whether the key is a resize option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "resize_allowed" => self.resize_allowed = value.get_bool().or(self.resize_allowed),
+            "resize_up_threshold" => self.up_threshold = value.get_uint32().or(self.up_threshold),
+            "resize_down_threshold" => self.down_threshold = value.get_uint32().or(self.down_threshold),
+            // "WxH"
+            "scaled_size" => {
+                if let Some(scaled_size) = value.get_string() {
+                    let size = scaled_size.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+                    self.scaled_size = Some(size.ok_or_else(|| unsupported_error!(scaled_size))?);
+                }
+            }
+            "scaling_mode" => {
+                if let Some(scaling_mode) = value.get_string() {
+                    self.scaling_mode = Some(scaling_mode.parse()?);
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub(super) fn apply(&self, cfg: &mut vpx_codec_enc_cfg_t) {
+        if let Some(resize_allowed) = self.resize_allowed {
+            cfg.rc_resize_allowed = resize_allowed as u32;
+        }
+
+        if let Some(up_threshold) = self.up_threshold {
+            cfg.rc_resize_up_thresh = up_threshold;
+        }
+
+        if let Some(down_threshold) = self.down_threshold {
+            cfg.rc_resize_down_thresh = down_threshold;
+        }
+
+        if let Some((width, height)) = self.scaled_size {
+            cfg.rc_scaled_width = width;
+            cfg.rc_scaled_height = height;
+        }
+    }
+}
+
+// Coded size from the header of a VP8 keyframe, inter frames keep the size of
+// the last keyframe
+pub(super) fn vp8_key_frame_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a] {
+        return None;
+    }
+
+    let width = u16::from_le_bytes([data[6], data[7]]) & 0x3fff;
+    let height = u16::from_le_bytes([data[8], data[9]]) & 0x3fff;
+
+    Some((width as u32, height as u32))
+}
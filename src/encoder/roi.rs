@@ -0,0 +1,80 @@
+use std::os::raw::c_int;
+
+use media_core::{error::Error, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReferenceFrame {
+    Intra,
+    Last,
+    Golden,
+    AltRef,
+}
+
+impl ReferenceFrame {
+    pub(super) fn as_vpx(reference: Option<ReferenceFrame>) -> c_int {
+        match reference {
+            None => -1,
+            Some(ReferenceFrame::Intra) => 0,
+            Some(ReferenceFrame::Last) => 1,
+            Some(ReferenceFrame::Golden) => 2,
+            Some(ReferenceFrame::AltRef) => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoiSegment {
+    // Quantizer offset, in the range -63 to 63 for VP8 and -255 to 255 for VP9
+    pub delta_q: i32,
+    // Loop filter level offset, in the range -63 to 63
+    pub delta_lf: i32,
+    // VP9 only
+    pub skip: bool,
+    pub ref_frame: Option<ReferenceFrame>,
+    // VP8 only, blocks below this difference are not coded
+    pub static_threshold: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct RoiMap {
+    pub cols: u32,
+    pub rows: u32,
+    // Segment index of each block in raster order
+    pub map: Vec<u8>,
+    pub segments: Vec<RoiSegment>,
+}
+
+impl RoiMap {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        Self {
+            cols,
+            rows,
+            map: vec![0; (cols * rows) as usize],
+            segments: vec![RoiSegment::default()],
+        }
+    }
+
+    pub(super) fn validate(&self, cols: u32, rows: u32, max_segments: usize, max_delta_q: i32) -> Result<()> {
+        if self.cols != cols || self.rows != rows {
+            return Err(Error::Invalid(format!("roi map is {}x{} blocks, encoder expects {}x{}", self.cols, self.rows, cols, rows).into()));
+        }
+
+        if self.map.len() != (cols * rows) as usize {
+            return Err(Error::Invalid(format!("roi map has {} blocks, expected {}", self.map.len(), cols * rows).into()));
+        }
+
+        if self.segments.is_empty() || self.segments.len() > max_segments {
+            return Err(Error::Invalid(format!("roi map must have 1 to {} segments", max_segments).into()));
+        }
+
+        if self.map.iter().any(|segment| *segment as usize >= self.segments.len()) {
+            return Err(Error::Invalid("roi map references an undefined segment".into()));
+        }
+
+        if self.segments.iter().any(|segment| !(-max_delta_q..=max_delta_q).contains(&segment.delta_q) || !(-63..=63).contains(&segment.delta_lf)) {
+            return Err(Error::Invalid("roi segment delta out of range".into()));
+        }
+
+        Ok(())
+    }
+}
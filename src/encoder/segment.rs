@@ -0,0 +1,56 @@
+use std::os::raw::c_uint;
+
+use media_core::{invalid_param_error, Result};
+
+use crate::vpx_sys::{vpx_codec_enc_cfg_t, vpx_kf_mode, vpx_rational};
+
+// Keeps the automatic keyframes of libvpx off the segment grid, the forced
+// keyframe of each segment resets the distance
+const SCENE_CUT_MAX_DIST: c_uint = 9999;
+
+// Forces a keyframe on the first frame of each segment, the boundaries are
+// multiples of the segment duration from pts 0 so that renditions sharing the
+// time base align whatever frame they start at
+#[derive(Debug)]
+pub(super) struct SegmentKeyFrames {
+    // In time base units
+    duration: i64,
+    next_boundary: Option<i64>,
+}
+
+impl SegmentKeyFrames {
+    // The duration is in seconds
+    pub fn new(duration: f64, time_base: vpx_rational) -> Result<Self> {
+        let duration_ticks = (duration * time_base.den as f64 / time_base.num as f64).round() as i64;
+        if duration_ticks <= 0 {
+            return Err(invalid_param_error!(duration));
+        }
+
+        Ok(Self {
+            duration: duration_ticks,
+            next_boundary: None,
+        })
+    }
+
+    // Only scene changes leave libvpx keyframes inside a segment
+    pub fn configure(&self, cfg: &mut vpx_codec_enc_cfg_t, scene_cut_key_frames: bool) {
+        if scene_cut_key_frames {
+            cfg.kf_mode = vpx_kf_mode::VPX_KF_AUTO;
+            cfg.kf_min_dist = 0;
+            cfg.kf_max_dist = SCENE_CUT_MAX_DIST;
+        } else {
+            cfg.kf_mode = vpx_kf_mode::VPX_KF_DISABLED;
+        }
+    }
+
+    // Returns whether the frame starts a segment
+    pub fn is_boundary(&mut self, pts: i64) -> bool {
+        if self.next_boundary.is_some_and(|boundary| pts < boundary) {
+            return false;
+        }
+
+        self.next_boundary = Some((pts.div_euclid(self.duration) + 1) * self.duration);
+
+        true
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    collections::VecDeque,
+    mem::MaybeUninit,
+    os::raw::{c_int, c_uint, c_ulong},
+    ptr, slice,
+    sync::Arc,
+};
+
+use media_codec::{
+    codec::{Codec, CodecID},
+    encoder::{Encoder, VideoEncoder, VideoEncoderParameters},
+    packet::Packet,
+    CodecInformation, CodecParameters,
+};
+use media_core::{
+    buffer::BufferPool,
+    error::Error,
+    frame::SharedFrame,
+    invalid_param_error, none_param_error,
+    rational::Rational64,
+    unsupported_error,
+    variant::Variant,
+    video::{PixelFormat, VideoFrame},
+    Result,
+};
+
+use super::{
+    new_packet, set_frame_flags, speed,
+    timestamp::{self, Timestamps},
+    wrap_image, PacketInfo, Speed, VpxEncoderOptions,
+};
+use crate::{
+    vpx_error_string,
+    vpx_sys::{
+        self, vp8e_enc_control_id, vpx_codec_ctx_t, vpx_codec_cx_pkt_kind, vpx_codec_enc_cfg_t, vpx_codec_err_t::VPX_CODEC_OK, vpx_codec_iter_t,
+        vpx_enc_deadline_t, vpx_image_t, vpx_img_fmt, vpx_rational, VPX_DL_REALTIME, VPX_ENCODER_ABI_VERSION,
+    },
+};
+
+const MAX_SIMULCAST_STREAMS: usize = 3;
+
+// Options applied by the simulcast encoder, the others are rejected
+const SIMULCAST_OPTIONS: [&str; 4] = ["speed", "cpu_used", "deadline", "time_base"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SimulcastStream {
+    // Down scaling factor from the previous stream as numerator and
+    // denominator, such as (2, 1) for half the size, ignored for the first
+    // stream which keeps the input resolution
+    pub scale_factor: (u32, u32),
+    pub bit_rate: u64,
+}
+
+// VP8 encoder producing several resolutions of the same input, libvpx encodes
+// the lowest resolution first and the higher ones reuse its mode and motion
+// information
+pub struct VpxSimulcastEncoder {
+    // libvpx addresses the contexts and configs of the streams as arrays
+    ctx: Vec<vpx_codec_ctx_t>,
+    cfg: Vec<vpx_codec_enc_cfg_t>,
+    // Down scaled input of the streams after the first
+    images: Vec<*mut vpx_image_t>,
+    img_fmt: vpx_img_fmt,
+    deadline: vpx_enc_deadline_t,
+    timestamps: Timestamps,
+    packets: VecDeque<(Packet<'static>, PacketInfo)>,
+    packet_info: Option<PacketInfo>,
+}
+
+unsafe impl Send for VpxSimulcastEncoder {}
+unsafe impl Sync for VpxSimulcastEncoder {}
+
+impl Codec<VideoEncoder> for VpxSimulcastEncoder {
+    fn configure(&mut self, _params: Option<&CodecParameters>, options: Option<&Variant>) -> Result<()> {
+        if let Some(options) = options.and_then(|options| options.dict_iter()) {
+            for (key, value) in options {
+                self.set_option(key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Only the speed options apply once the encoder is created
+    fn set_option(&mut self, key: &str, value: &Variant) -> Result<()> {
+        match key {
+            "speed" => {
+                if let Some(speed) = value.get_string() {
+                    let (cpu_used, deadline) = speed.parse::<Speed>()?.settings(CodecID::VP8);
+
+                    self.set_cpu_used(cpu_used)?;
+                    self.deadline = deadline;
+                }
+            }
+            "cpu_used" => {
+                if let Some(cpu_used) = value.get_int32() {
+                    self.set_cpu_used(cpu_used)?;
+                }
+            }
+            "deadline" => {
+                if let Some(deadline) = speed::parse_deadline(value)? {
+                    self.deadline = deadline;
+                }
+            }
+            _ => return Err(unsupported_error!(key)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Encoder<VideoEncoder> for VpxSimulcastEncoder {
+    fn send_frame(&mut self, _config: &VideoEncoder, pool: Option<&Arc<BufferPool>>, frame: SharedFrame<VideoFrame<'static>>) -> Result<()> {
+        let frame = frame.read();
+        let guard = frame.map()?;
+        let planes = guard.planes().ok_or_else(|| Error::Invalid("frame data is not mappable".into()))?;
+        let desc = frame.descriptor();
+
+        let img_fmt = match desc.format {
+            PixelFormat::I420 => vpx_img_fmt::VPX_IMG_FMT_I420,
+            PixelFormat::YV12 => vpx_img_fmt::VPX_IMG_FMT_YV12,
+            _ => return Err(unsupported_error!(desc.format)),
+        };
+        if img_fmt != self.img_fmt || desc.width().get() != self.cfg[0].g_w || desc.height().get() != self.cfg[0].g_h {
+            return Err(Error::Invalid("frame does not match the encoder parameters".into()));
+        }
+
+        let mut images = vec![wrap_image(desc, &planes, img_fmt, 8)?];
+        for img in &self.images {
+            let img = unsafe { **img };
+            scale_image(images.last().unwrap(), &img);
+            images.push(img);
+        }
+
+        let (pts, duration) = self.timestamps.next(frame.pts, frame.duration, frame.time_base)?;
+
+        let ret = unsafe { vpx_sys::vpx_codec_encode(self.ctx.as_mut_ptr(), images.as_ptr(), pts, duration as c_ulong, 0, self.deadline) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        self.get_packets(pool);
+
+        Ok(())
+    }
+
+    fn receive_packet(&mut self, _config: &VideoEncoder, _pool: Option<&Arc<BufferPool>>) -> Result<Packet<'static>> {
+        let (packet, info) = self.packets.pop_front().ok_or_else(|| Error::Again("no packet available".into()))?;
+
+        self.packet_info = Some(info);
+
+        Ok(packet)
+    }
+
+    fn flush(&mut self, _config: &VideoEncoder) -> Result<()> {
+        loop {
+            let ret = unsafe { vpx_sys::vpx_codec_encode(self.ctx.as_mut_ptr(), ptr::null(), -1, 1, 0, self.deadline) };
+            if ret != VPX_CODEC_OK {
+                return Err(Error::Invalid(vpx_error_string(ret)));
+            }
+
+            let packets = self.packets.len();
+            self.get_packets(None);
+
+            if self.packets.len() == packets {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl VpxSimulcastEncoder {
+    pub fn new(params: &VideoEncoderParameters, streams: &[SimulcastStream], options: Option<&Variant>) -> Result<Self> {
+        if streams.len() < 2 || streams.len() > MAX_SIMULCAST_STREAMS {
+            return Err(invalid_param_error!(streams.len()));
+        }
+
+        let mut opts = VpxEncoderOptions::default();
+        if let Some(options) = options.and_then(|options| options.dict_iter()) {
+            for (key, value) in options {
+                if !SIMULCAST_OPTIONS.contains(&key.as_str()) {
+                    return Err(unsupported_error!(key));
+                }
+
+                opts.update(key, value)?;
+            }
+        }
+
+        let pixel_format = params.video.format.unwrap_or(PixelFormat::I420);
+        let img_fmt = match pixel_format {
+            PixelFormat::I420 => vpx_img_fmt::VPX_IMG_FMT_I420,
+            PixelFormat::YV12 => vpx_img_fmt::VPX_IMG_FMT_YV12,
+            _ => return Err(unsupported_error!(pixel_format)),
+        };
+
+        let width = params.video.width.ok_or_else(|| none_param_error!(params.video.width))?;
+        let height = params.video.height.ok_or_else(|| none_param_error!(params.video.height))?;
+
+        let iface = unsafe { vpx_sys::vpx_codec_vp8_cx() };
+        let mut base_cfg = MaybeUninit::zeroed();
+        let ret = unsafe { vpx_sys::vpx_codec_enc_config_default(iface, base_cfg.as_mut_ptr(), 0) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        let mut base_cfg: vpx_codec_enc_cfg_t = unsafe { base_cfg.assume_init() };
+        base_cfg.g_timebase = timestamp::encoder_time_base(opts.time_base)?;
+
+        let mut cfg = Vec::with_capacity(streams.len());
+        let (mut stream_width, mut stream_height) = (width.get(), height.get());
+
+        for (i, stream) in streams.iter().enumerate() {
+            if i > 0 {
+                let (num, den) = stream.scale_factor;
+                if den == 0 || num < den {
+                    return Err(invalid_param_error!(stream.scale_factor));
+                }
+
+                stream_width = (stream_width * den).div_ceil(num);
+                stream_height = (stream_height * den).div_ceil(num);
+            }
+
+            let mut stream_cfg = base_cfg;
+            stream_cfg.g_w = stream_width;
+            stream_cfg.g_h = stream_height;
+            stream_cfg.rc_target_bitrate = (stream.bit_rate / 1000) as c_uint;
+            cfg.push(stream_cfg);
+        }
+
+        // The factor of each stream to the next one, the last one is unused
+        let mut dsf: Vec<_> = streams[1..]
+            .iter()
+            .map(|stream| vpx_rational {
+                num: stream.scale_factor.0 as c_int,
+                den: stream.scale_factor.1 as c_int,
+            })
+            .chain([vpx_rational {
+                num: 1,
+                den: 1,
+            }])
+            .collect();
+
+        let mut ctx: Vec<vpx_codec_ctx_t> = (0..streams.len()).map(|_| unsafe { MaybeUninit::zeroed().assume_init() }).collect();
+        let ver = VPX_ENCODER_ABI_VERSION as i32;
+        let ret = unsafe {
+            vpx_sys::vpx_codec_enc_init_multi_ver(ctx.as_mut_ptr(), iface, cfg.as_mut_ptr(), streams.len() as c_int, 0, dsf.as_mut_ptr(), ver)
+        };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        let (cpu_used, deadline) = match opts.speed {
+            Some(speed) => {
+                let (cpu_used, deadline) = speed.settings(CodecID::VP8);
+                (Some(cpu_used), deadline)
+            }
+            None => (None, VPX_DL_REALTIME as vpx_enc_deadline_t),
+        };
+
+        let timestamps = Timestamps::new(cfg[0].g_timebase, params.video.frame_rate);
+
+        let mut encoder = Self {
+            ctx,
+            cfg,
+            images: Vec::new(),
+            img_fmt,
+            deadline: opts.deadline.unwrap_or(deadline),
+            timestamps,
+            packets: VecDeque::new(),
+            packet_info: None,
+        };
+
+        for i in 1..encoder.cfg.len() {
+            let img = unsafe { vpx_sys::vpx_img_alloc(ptr::null_mut(), vpx_img_fmt::VPX_IMG_FMT_I420, encoder.cfg[i].g_w, encoder.cfg[i].g_h, 32) };
+            if img.is_null() {
+                return Err(Error::Invalid("failed to allocate image".into()));
+            }
+
+            encoder.images.push(img);
+        }
+
+        if let Some(cpu_used) = opts.cpu_used.or(cpu_used) {
+            encoder.set_cpu_used(cpu_used)?;
+        }
+
+        Ok(encoder)
+    }
+
+    pub fn packet_info(&self) -> Option<&PacketInfo> {
+        self.packet_info.as_ref()
+    }
+
+    // Number of columns and rows of the stream
+    pub fn stream_size(&self, index: usize) -> Option<(u32, u32)> {
+        self.cfg.get(index).map(|cfg| (cfg.g_w, cfg.g_h))
+    }
+
+    pub fn set_stream_bit_rate(&mut self, index: usize, bit_rate: u64) -> Result<()> {
+        if index >= self.cfg.len() {
+            return Err(invalid_param_error!(index));
+        }
+
+        self.cfg[index].rc_target_bitrate = (bit_rate / 1000) as c_uint;
+
+        let ret = unsafe { vpx_sys::vpx_codec_enc_config_set(&mut self.ctx[index], &self.cfg[index]) };
+        if ret != VPX_CODEC_OK {
+            return Err(Error::Invalid(vpx_error_string(ret)));
+        }
+
+        Ok(())
+    }
+
+    // Applied to every stream
+    pub fn set_cpu_used(&mut self, cpu_used: i32) -> Result<()> {
+        speed::validate_cpu_used(CodecID::VP8, cpu_used)?;
+
+        for ctx in &mut self.ctx {
+            let ret = unsafe { vpx_sys::vpx_codec_control_(ctx, vp8e_enc_control_id::VP8E_SET_CPUUSED as c_int, cpu_used) };
+            if ret != VPX_CODEC_OK {
+                return Err(Error::Invalid(vpx_error_string(ret)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_packets(&mut self, pool: Option<&Arc<BufferPool>>) {
+        let time_base = Rational64::new(self.cfg[0].g_timebase.num as i64, self.cfg[0].g_timebase.den as i64);
+
+        for (index, ctx) in self.ctx.iter_mut().enumerate() {
+            let mut iter: vpx_codec_iter_t = ptr::null();
+
+            loop {
+                let pkt = unsafe { vpx_sys::vpx_codec_get_cx_data(ctx, &mut iter) };
+                if pkt.is_null() {
+                    break;
+                }
+
+                let pkt = unsafe { &*pkt };
+                if pkt.kind != vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT {
+                    continue;
+                }
+
+                let frame = unsafe { &pkt.data.frame };
+                let data = unsafe { slice::from_raw_parts(frame.buf as *const u8, frame.sz) };
+
+                let mut packet = new_packet(data, pool);
+                packet.pts = Some(frame.pts);
+                packet.dts = Some(frame.pts);
+                packet.duration = Some(frame.duration as i64);
+                packet.time_base = Some(time_base);
+
+                let mut info = PacketInfo {
+                    coded_size: Some((self.cfg[index].g_w, self.cfg[index].g_h)),
+                    stream_index: Some(index as u8),
+                    ..Default::default()
+                };
+
+                set_frame_flags(frame, &mut packet, &mut info);
+
+                self.packets.push_back((packet, info));
+            }
+        }
+    }
+}
+
+impl Drop for VpxSimulcastEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            for ctx in &mut self.ctx {
+                vpx_sys::vpx_codec_destroy(ctx);
+            }
+
+            for img in &self.images {
+                vpx_sys::vpx_img_free(*img);
+            }
+        }
+    }
+}
+
+impl CodecInformation for VpxSimulcastEncoder {
+    fn id(&self) -> CodecID {
+        CodecID::VP8
+    }
+
+    fn name(&self) -> &'static str {
+        "vp8-simulcast-enc"
+    }
+}
+
+// Area average down scaling of the 8 bit 4:2:0 planes
+fn scale_image(src: &vpx_image_t, dst: &vpx_image_t) {
+    for plane in 0..3 {
+        let shift = (plane > 0) as u32;
+        let (src_width, src_height) = (((src.d_w + shift) >> shift) as usize, ((src.d_h + shift) >> shift) as usize);
+        let (dst_width, dst_height) = (((dst.d_w + shift) >> shift) as usize, ((dst.d_h + shift) >> shift) as usize);
+        let (src_stride, dst_stride) = (src.stride[plane] as usize, dst.stride[plane] as usize);
+
+        let src_data = unsafe { slice::from_raw_parts(src.planes[plane], src_stride * (src_height - 1) + src_width) };
+        let dst_data = unsafe { slice::from_raw_parts_mut(dst.planes[plane], dst_stride * (dst_height - 1) + dst_width) };
+
+        for y in 0..dst_height {
+            let y0 = y * src_height / dst_height;
+            let y1 = ((y + 1) * src_height / dst_height).max(y0 + 1);
+
+            for x in 0..dst_width {
+                let x0 = x * src_width / dst_width;
+                let x1 = ((x + 1) * src_width / dst_width).max(x0 + 1);
+                let sum: u32 = (y0..y1).flat_map(|sy| &src_data[sy * src_stride + x0..sy * src_stride + x1]).map(|sample| *sample as u32).sum();
+                let count = ((y1 - y0) * (x1 - x0)) as u32;
+
+                dst_data[y * dst_stride + x] = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::{os::raw::c_int, str::FromStr};
+
+use media_codec::codec::CodecID;
+use media_core::{error::Error, invalid_param_error, unsupported_error, variant::Variant, Result};
+
+use crate::vpx_sys::{vpx_enc_deadline_t, VPX_DL_BEST_QUALITY, VPX_DL_GOOD_QUALITY, VPX_DL_REALTIME};
+
+// From the slowest, best compressing setting to the fastest
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Speed {
+    Archival,
+    Quality,
+    Balanced,
+    Fast,
+    Realtime,
+}
+
+impl FromStr for Speed {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "archival" => Ok(Speed::Archival),
+            "quality" => Ok(Speed::Quality),
+            "balanced" => Ok(Speed::Balanced),
+            "fast" => Ok(Speed::Fast),
+            "realtime" => Ok(Speed::Realtime),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+impl Speed {
+    // CPU usage and deadline of each codec for the speed
+    pub(super) fn settings(&self, id: CodecID) -> (c_int, vpx_enc_deadline_t) {
+        let vp8 = id == CodecID::VP8;
+
+        match (self, vp8) {
+            (Speed::Archival, _) => (0, VPX_DL_BEST_QUALITY as vpx_enc_deadline_t),
+            (Speed::Quality, true) => (0, VPX_DL_GOOD_QUALITY as vpx_enc_deadline_t),
+            (Speed::Quality, false) => (1, VPX_DL_GOOD_QUALITY as vpx_enc_deadline_t),
+            (Speed::Balanced, _) => (2, VPX_DL_GOOD_QUALITY as vpx_enc_deadline_t),
+            (Speed::Fast, _) => (4, VPX_DL_GOOD_QUALITY as vpx_enc_deadline_t),
+            // Negative VP8 values let the encoder adapt its speed to the frame budget
+            (Speed::Realtime, true) => (-6, VPX_DL_REALTIME as vpx_enc_deadline_t),
+            (Speed::Realtime, false) => (7, VPX_DL_REALTIME as vpx_enc_deadline_t),
+        }
+    }
+}
+
+pub(super) fn validate_cpu_used(id: CodecID, cpu_used: c_int) -> Result<()> {
+    let max = if id == CodecID::VP8 {
+        16
+    } else {
+        9
+    };
+
+    if !(-max..=max).contains(&cpu_used) {
+        return Err(invalid_param_error!(cpu_used));
+    }
+
+    Ok(())
+}
+
+// Accept "realtime", "good" or "best", or a time budget in microseconds
+pub(super) fn parse_deadline(value: &Variant) -> Result<Option<vpx_enc_deadline_t>> {
+    if let Some(deadline) = value.get_string() {
+        let deadline = match deadline.as_str() {
+            "realtime" => VPX_DL_REALTIME,
+            "good" => VPX_DL_GOOD_QUALITY,
+            "best" => VPX_DL_BEST_QUALITY,
+            _ => return Err(unsupported_error!(deadline)),
+        };
+
+        return Ok(Some(deadline as vpx_enc_deadline_t));
+    }
+
+    Ok(value.get_uint64().map(|deadline| deadline as vpx_enc_deadline_t))
+}
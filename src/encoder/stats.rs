@@ -0,0 +1,33 @@
+use crate::vpx_sys::vpx_codec_cx_pkt__bindgen_ty_1_vpx_psnr_pkt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Psnr {
+    pub total: f64,
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl From<&vpx_codec_cx_pkt__bindgen_ty_1_vpx_psnr_pkt> for Psnr {
+    fn from(pkt: &vpx_codec_cx_pkt__bindgen_ty_1_vpx_psnr_pkt) -> Self {
+        Self {
+            total: pkt.psnr[0],
+            y: pkt.psnr[1],
+            u: pkt.psnr[2],
+            v: pkt.psnr[3],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncodeStats {
+    pub psnr: Option<Psnr>,
+    // Quantizer on the 0 to 63 scale of the rate control settings
+    pub quantizer: Option<i32>,
+    // Quantizer of each layer of the superframe in spatial layer major order
+    pub layer_quantizers: Vec<i32>,
+    pub loop_filter_level: Option<i32>,
+    pub size: usize,
+    pub key_frame: bool,
+    pub droppable: bool,
+}
@@ -0,0 +1,507 @@
+use std::{
+    os::raw::{c_int, c_uint},
+    str::FromStr,
+};
+
+use media_codec::codec::CodecID;
+use media_core::{error::Error, unsupported_error, Result};
+use smallvec::SmallVec;
+
+use crate::vpx_sys::{
+    vpx_codec_enc_cfg_t, vpx_rc_mode, vpx_svc_extra_cfg_t, vpx_svc_layer_id_t, vpx_svc_ref_frame_config_t, VP8_EFLAG_NO_REF_ARF, VP8_EFLAG_NO_REF_GF,
+    VP8_EFLAG_NO_REF_LAST, VP8_EFLAG_NO_UPD_ARF, VP8_EFLAG_NO_UPD_GF, VP8_EFLAG_NO_UPD_LAST, VP9E_TEMPORAL_LAYERING_MODE,
+    VPX_ERROR_RESILIENT_DEFAULT, VPX_SS_MAX_LAYERS,
+};
+
+// SVC_INTER_LAYER_PRED values of VP9E_SET_SVC_INTER_LAYER_PRED
+pub(super) const INTER_LAYER_PRED_ON: c_int = 0;
+pub(super) const INTER_LAYER_PRED_OFF_NONKEY: c_int = 2;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ScalabilityMode {
+    #[default]
+    L1T1,
+    L1T2,
+    L1T3,
+    L2T1,
+    L2T2,
+    L2T3,
+    L3T1,
+    L3T2,
+    L3T3,
+    L2T1_KEY,
+    L2T2_KEY,
+    L2T3_KEY,
+    L3T1_KEY,
+    L3T2_KEY,
+    L3T3_KEY,
+}
+
+impl ScalabilityMode {
+    pub fn spatial_layers(&self) -> u32 {
+        use ScalabilityMode::*;
+
+        match self {
+            L1T1 | L1T2 | L1T3 => 1,
+            L2T1 | L2T2 | L2T3 | L2T1_KEY | L2T2_KEY | L2T3_KEY => 2,
+            L3T1 | L3T2 | L3T3 | L3T1_KEY | L3T2_KEY | L3T3_KEY => 3,
+        }
+    }
+
+    pub fn temporal_layers(&self) -> u32 {
+        use ScalabilityMode::*;
+
+        match self {
+            L1T1 | L2T1 | L3T1 | L2T1_KEY | L3T1_KEY => 1,
+            L1T2 | L2T2 | L3T2 | L2T2_KEY | L3T2_KEY => 2,
+            L1T3 | L2T3 | L3T3 | L2T3_KEY | L3T3_KEY => 3,
+        }
+    }
+
+    // Spatial layers predict from lower spatial layers only on key pictures
+    pub fn is_key_shift(&self) -> bool {
+        use ScalabilityMode::*;
+
+        matches!(self, L2T1_KEY | L2T2_KEY | L2T3_KEY | L3T1_KEY | L3T2_KEY | L3T3_KEY)
+    }
+}
+
+impl FromStr for ScalabilityMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use ScalabilityMode::*;
+
+        match s {
+            "L1T1" => Ok(L1T1),
+            "L1T2" => Ok(L1T2),
+            "L1T3" => Ok(L1T3),
+            "L2T1" => Ok(L2T1),
+            "L2T2" => Ok(L2T2),
+            "L2T3" => Ok(L2T3),
+            "L3T1" => Ok(L3T1),
+            "L3T2" => Ok(L3T2),
+            "L3T3" => Ok(L3T3),
+            "L2T1_KEY" => Ok(L2T1_KEY),
+            "L2T2_KEY" => Ok(L2T2_KEY),
+            "L2T3_KEY" => Ok(L2T3_KEY),
+            "L3T1_KEY" => Ok(L3T1_KEY),
+            "L3T2_KEY" => Ok(L3T2_KEY),
+            "L3T3_KEY" => Ok(L3T3_KEY),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpatialLayerInfo {
+    pub spatial_layer_id: u8,
+    pub width: u32,
+    pub height: u32,
+    // Size in bytes of the layer frame within the superframe
+    pub size: usize,
+}
+
+struct TemporalLayerPattern {
+    layer_ids: &'static [u8],
+    flags: &'static [u32],
+    rate_decimators: &'static [c_uint],
+    // Cumulative share of the target bitrate, in percent
+    bitrate_ratios: &'static [c_uint],
+}
+
+const L1T1_PATTERN: TemporalLayerPattern = TemporalLayerPattern {
+    layer_ids: &[0],
+    flags: &[VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF],
+    rate_decimators: &[1],
+    bitrate_ratios: &[100],
+};
+
+const L1T2_PATTERN: TemporalLayerPattern = TemporalLayerPattern {
+    layer_ids: &[0, 1],
+    flags: &[
+        VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+        VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_LAST | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+    ],
+    rate_decimators: &[2, 1],
+    bitrate_ratios: &[60, 100],
+};
+
+const L1T3_PATTERN: TemporalLayerPattern = TemporalLayerPattern {
+    layer_ids: &[0, 2, 1, 2],
+    flags: &[
+        VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+        VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_LAST | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+        VP8_EFLAG_NO_REF_GF | VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_LAST | VP8_EFLAG_NO_UPD_ARF,
+        VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_LAST | VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+    ],
+    rate_decimators: &[4, 2, 1],
+    bitrate_ratios: &[40, 60, 100],
+};
+
+// Reference buffers in LAST, GOLDEN, ALTREF order
+const REFERENCE_FLAGS: [u32; 3] = [VP8_EFLAG_NO_REF_LAST, VP8_EFLAG_NO_REF_GF, VP8_EFLAG_NO_REF_ARF];
+const UPDATE_FLAGS: [u32; 3] = [VP8_EFLAG_NO_UPD_LAST, VP8_EFLAG_NO_UPD_GF, VP8_EFLAG_NO_UPD_ARF];
+
+// VP9 buffer slots: the LAST and GOLDEN buffers of the temporal pattern are
+// kept per spatial layer in slots 0 to 2 and 5 to 7, slots 3 and 4 carry the
+// inter-layer references of frames that do not update a temporal buffer
+const VP9_LAST_SLOT: c_int = 0;
+const VP9_GOLDEN_SLOT: c_int = VPX_SS_MAX_LAYERS as c_int;
+const VP9_INTER_LAYER_SLOT: c_int = 3;
+
+// Share of the target bitrate of each spatial layer, proportional to its
+// scaling factor
+const SPATIAL_SCALING_FACTORS: [&[(c_int, c_int)]; 3] = [&[(1, 1)], &[(1, 2), (1, 1)], &[(1, 4), (1, 2), (1, 1)]];
+
+pub(super) struct LayerStructure {
+    mode: ScalabilityMode,
+    pattern: &'static TemporalLayerPattern,
+    index: usize,
+    // Temporal layer that last updated each reference buffer
+    buffer_layers: [u8; 3],
+}
+
+impl LayerStructure {
+    pub(super) fn new(mode: ScalabilityMode) -> Option<Self> {
+        if mode == ScalabilityMode::L1T1 {
+            return None;
+        }
+
+        let pattern = match mode.temporal_layers() {
+            1 => &L1T1_PATTERN,
+            2 => &L1T2_PATTERN,
+            _ => &L1T3_PATTERN,
+        };
+
+        Some(Self {
+            mode,
+            pattern,
+            index: 0,
+            buffer_layers: [0; 3],
+        })
+    }
+
+    pub(super) fn mode(&self) -> ScalabilityMode {
+        self.mode
+    }
+
+    pub(super) fn spatial_layers(&self) -> usize {
+        self.mode.spatial_layers() as usize
+    }
+
+    pub(super) fn temporal_layers(&self) -> usize {
+        self.pattern.rate_decimators.len()
+    }
+
+    pub(super) fn configure(&self, id: CodecID, cfg: &mut vpx_codec_enc_cfg_t) {
+        let pattern = self.pattern;
+        let temporal_layers = self.temporal_layers();
+
+        cfg.ts_number_layers = temporal_layers as c_uint;
+        cfg.ts_periodicity = pattern.layer_ids.len() as c_uint;
+        for (i, layer_id) in pattern.layer_ids.iter().enumerate() {
+            cfg.ts_layer_id[i] = *layer_id as c_uint;
+        }
+        cfg.ts_rate_decimator[..temporal_layers].copy_from_slice(pattern.rate_decimators);
+        self.configure_bitrate(cfg);
+
+        cfg.rc_end_usage = vpx_rc_mode::VPX_CBR;
+        cfg.g_lag_in_frames = 0;
+
+        if id == CodecID::VP8 {
+            cfg.g_error_resilient = VPX_ERROR_RESILIENT_DEFAULT;
+        } else {
+            cfg.ss_number_layers = self.spatial_layers() as c_uint;
+            cfg.temporal_layering_mode = VP9E_TEMPORAL_LAYERING_MODE::VP9E_TEMPORAL_LAYERING_MODE_BYPASS as c_int;
+        }
+    }
+
+    pub(super) fn configure_bitrate(&self, cfg: &mut vpx_codec_enc_cfg_t) {
+        let spatial_layers = self.spatial_layers();
+        let temporal_layers = self.temporal_layers();
+        let scaling_factors = SPATIAL_SCALING_FACTORS[spatial_layers - 1];
+        let weights: SmallVec<[c_uint; 3]> = scaling_factors.iter().map(|(num, den)| (num * 4 / den) as c_uint).collect();
+        let total_weight: c_uint = weights.iter().sum();
+        let target_bitrate = cfg.rc_target_bitrate;
+
+        cfg.ts_target_bitrate = [0; 5];
+
+        for (sl, weight) in weights.iter().enumerate() {
+            let spatial_bitrate = (target_bitrate as u64 * *weight as u64 / total_weight as u64) as c_uint;
+
+            cfg.ss_target_bitrate[sl] = spatial_bitrate;
+
+            for (tl, ratio) in self.pattern.bitrate_ratios.iter().enumerate() {
+                let bitrate = spatial_bitrate * ratio / 100;
+                cfg.layer_target_bitrate[sl * temporal_layers + tl] = bitrate;
+                cfg.ts_target_bitrate[tl] += bitrate;
+            }
+        }
+    }
+
+    pub(super) fn svc_parameters(&self, cfg: &vpx_codec_enc_cfg_t) -> vpx_svc_extra_cfg_t {
+        let mut params = vpx_svc_extra_cfg_t {
+            max_quantizers: [0; 12],
+            min_quantizers: [0; 12],
+            scaling_factor_num: [1; 12],
+            scaling_factor_den: [1; 12],
+            speed_per_layer: [0; 12],
+            temporal_layering_mode: VP9E_TEMPORAL_LAYERING_MODE::VP9E_TEMPORAL_LAYERING_MODE_BYPASS as c_int,
+            loopfilter_ctrl: [0; 12],
+        };
+
+        let temporal_layers = self.temporal_layers();
+        let scaling_factors = SPATIAL_SCALING_FACTORS[self.spatial_layers() - 1];
+
+        for (sl, (num, den)) in scaling_factors.iter().enumerate() {
+            params.scaling_factor_num[sl] = *num;
+            params.scaling_factor_den[sl] = *den;
+
+            for tl in 0..temporal_layers {
+                params.max_quantizers[sl * temporal_layers + tl] = cfg.rc_max_quantizer as c_int;
+                params.min_quantizers[sl * temporal_layers + tl] = cfg.rc_min_quantizer as c_int;
+            }
+        }
+
+        params
+    }
+
+    pub(super) fn inter_layer_pred(&self) -> c_int {
+        if self.mode.is_key_shift() {
+            INTER_LAYER_PRED_OFF_NONKEY
+        } else {
+            INTER_LAYER_PRED_ON
+        }
+    }
+
+    pub(super) fn layer_id(&self) -> u8 {
+        self.pattern.layer_ids[self.index]
+    }
+
+    pub(super) fn flags(&self) -> u32 {
+        self.pattern.flags[self.index]
+    }
+
+    // The Y bit of RFC 7741, an upper layer frame referencing base layer frames
+    // only
+    pub(super) fn is_layer_sync(&self) -> bool {
+        let flags = self.flags();
+
+        self.layer_id() > 0 && REFERENCE_FLAGS.iter().zip(self.buffer_layers).all(|(no_ref, buffer_layer)| flags & no_ref != 0 || buffer_layer == 0)
+    }
+
+    pub(super) fn svc_layer_id(&self) -> vpx_svc_layer_id_t {
+        let layer_id = self.layer_id() as c_int;
+
+        vpx_svc_layer_id_t {
+            spatial_layer_id: 0,
+            temporal_layer_id: layer_id,
+            temporal_layer_id_per_spatial: [layer_id; 5],
+        }
+    }
+
+    // Translate the temporal pattern into VP9 buffer slots for every spatial
+    // layer, golden is used as the inter-layer reference
+    pub(super) fn ref_frame_config(&self, key_frame: bool) -> vpx_svc_ref_frame_config_t {
+        let mut config = vpx_svc_ref_frame_config_t {
+            lst_fb_idx: [0; 5],
+            gld_fb_idx: [0; 5],
+            alt_fb_idx: [0; 5],
+            update_buffer_slot: [0; 5],
+            update_last: [0; 5],
+            update_golden: [0; 5],
+            update_alt_ref: [0; 5],
+            reference_last: [0; 5],
+            reference_golden: [0; 5],
+            reference_alt_ref: [0; 5],
+            duration: [0; 5],
+        };
+
+        let flags = self.flags();
+        let inter_layer_pred = key_frame || !self.mode.is_key_shift();
+        let mut inter_layer_slot = None;
+
+        for sl in 0..self.spatial_layers() {
+            let last_slot = VP9_LAST_SLOT + sl as c_int;
+            let golden_slot = VP9_GOLDEN_SLOT + sl as c_int;
+
+            config.lst_fb_idx[sl] = last_slot;
+            config.alt_fb_idx[sl] = golden_slot;
+            config.gld_fb_idx[sl] = inter_layer_slot.unwrap_or(last_slot);
+
+            if key_frame {
+                config.update_buffer_slot[sl] = 1 << last_slot;
+            } else {
+                config.reference_last[sl] = (flags & VP8_EFLAG_NO_REF_LAST == 0) as c_int;
+                config.reference_alt_ref[sl] = (flags & VP8_EFLAG_NO_REF_GF == 0) as c_int;
+
+                if flags & VP8_EFLAG_NO_UPD_LAST == 0 {
+                    config.update_buffer_slot[sl] |= 1 << last_slot;
+                }
+                if flags & VP8_EFLAG_NO_UPD_GF == 0 {
+                    config.update_buffer_slot[sl] |= 1 << golden_slot;
+                }
+            }
+
+            if inter_layer_pred && inter_layer_slot.is_some() {
+                config.reference_golden[sl] = 1;
+            }
+
+            // Frames that update no buffer still need to be kept for the next
+            // spatial layer to predict from
+            inter_layer_slot = if config.update_buffer_slot[sl] & (1 << last_slot) != 0 {
+                Some(last_slot)
+            } else if config.update_buffer_slot[sl] & (1 << golden_slot) != 0 {
+                Some(golden_slot)
+            } else {
+                let slot = VP9_INTER_LAYER_SLOT + (sl % 2) as c_int;
+                if inter_layer_pred && sl + 1 < self.spatial_layers() {
+                    config.update_buffer_slot[sl] |= 1 << slot;
+                }
+                Some(slot)
+            };
+        }
+
+        config
+    }
+
+    pub(super) fn restart(&mut self) {
+        self.buffer_layers = [0; 3];
+        self.index = 0;
+    }
+
+    // Advance the pattern after a frame has been encoded, keyframes restart it
+    // at the base layer
+    pub(super) fn advance(&mut self, key_frame: bool) {
+        if key_frame {
+            self.restart();
+        } else {
+            let layer_id = self.layer_id();
+            let flags = self.flags();
+
+            for (no_update, buffer_layer) in UPDATE_FLAGS.iter().zip(self.buffer_layers.iter_mut()) {
+                if flags & no_update == 0 {
+                    *buffer_layer = layer_id;
+                }
+            }
+        }
+
+        self.index = (self.index + 1) % self.pattern.layer_ids.len();
+    }
+}
+
+// Split a VP9 superframe into the sizes of its frames using the superframe
+// index, a plain frame yields its own size
+pub(super) fn superframe_sizes(data: &[u8]) -> SmallVec<[usize; 8]> {
+    let mut sizes = SmallVec::new();
+
+    if let Some(&marker) = data.last() {
+        if marker & 0xe0 == 0xc0 {
+            let frames = (marker & 0x7) as usize + 1;
+            let mag = ((marker >> 3) & 0x3) as usize + 1;
+            let index_size = 2 + mag * frames;
+
+            if data.len() >= index_size && data[data.len() - index_size] == marker {
+                let index = &data[data.len() - index_size + 1..];
+
+                for frame in 0..frames {
+                    let bytes = &index[frame * mag..(frame + 1) * mag];
+                    sizes.push(bytes.iter().rev().fold(0, |size, byte| (size << 8) | *byte as usize));
+                }
+
+                return sizes;
+            }
+        }
+    }
+
+    sizes.push(data.len());
+
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPATIAL_MODES: [ScalabilityMode; 12] = [
+        ScalabilityMode::L2T1,
+        ScalabilityMode::L2T2,
+        ScalabilityMode::L2T3,
+        ScalabilityMode::L3T1,
+        ScalabilityMode::L3T2,
+        ScalabilityMode::L3T3,
+        ScalabilityMode::L2T1_KEY,
+        ScalabilityMode::L2T2_KEY,
+        ScalabilityMode::L2T3_KEY,
+        ScalabilityMode::L3T1_KEY,
+        ScalabilityMode::L3T2_KEY,
+        ScalabilityMode::L3T3_KEY,
+    ];
+
+    #[test]
+    fn layer_sync_references_base_layer_only() {
+        let mut layers = LayerStructure::new(ScalabilityMode::L1T3).unwrap();
+        let mut sync = Vec::new();
+
+        for _ in 0..layers.pattern.layer_ids.len() {
+            sync.push(layers.is_layer_sync());
+            layers.advance(false);
+        }
+
+        // The last TL2 frame references the TL1 frame through golden
+        assert_eq!(sync, [false, true, true, false]);
+    }
+
+    #[test]
+    fn ref_frame_config_slots_do_not_overlap() {
+        for mode in SPATIAL_MODES {
+            let mut layers = LayerStructure::new(mode).unwrap();
+            let spatial_layers = layers.spatial_layers();
+
+            for index in 0..layers.pattern.layer_ids.len() {
+                layers.index = index;
+
+                for key_frame in [false, true] {
+                    let config = layers.ref_frame_config(key_frame);
+
+                    for sl in 0..spatial_layers {
+                        // The temporal references of the layer are kept across superframes
+                        let temporal_slots = (1 << config.lst_fb_idx[sl]) | (1 << config.alt_fb_idx[sl]);
+
+                        for other in (0..spatial_layers).filter(|other| *other != sl) {
+                            assert_eq!(
+                                config.update_buffer_slot[other] & temporal_slots,
+                                0,
+                                "{:?} index {} key {}: layer {} overwrites the references of layer {}",
+                                mode,
+                                index,
+                                key_frame,
+                                other,
+                                sl
+                            );
+                        }
+
+                        // The inter-layer reference is written by the layer below only
+                        if config.reference_golden[sl] != 0 {
+                            let slot = config.gld_fb_idx[sl];
+                            let writers: Vec<usize> =
+                                (0..spatial_layers).filter(|other| config.update_buffer_slot[*other] & (1 << slot) != 0).collect();
+
+                            assert!(
+                                !writers.is_empty() && writers.iter().all(|writer| *writer == sl - 1),
+                                "{:?} index {} key {}: slot {} of layer {}",
+                                mode,
+                                index,
+                                key_frame,
+                                slot,
+                                sl
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::thread;
+
+use media_codec::codec::CodecID;
+use media_core::{unsupported_error, variant::Variant, Result};
+
+// VP9 tiles are at least 256 pixels wide and at most 64 per row
+const MIN_TILE_WIDTH: u32 = 256;
+const MAX_TILE_COLUMNS_LOG2: u32 = 6;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Auto<T> {
+    Auto,
+    Value(T),
+}
+
+impl Auto<u32> {
+    // Accept "auto" or a number
+    pub(super) fn parse(value: &Variant) -> Result<Option<Self>> {
+        if let Some(value) = value.get_string() {
+            return match value.as_str() {
+                "auto" => Ok(Some(Auto::Auto)),
+                _ => Err(unsupported_error!(value)),
+            };
+        }
+
+        Ok(value.get_uint32().map(Auto::Value))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ThreadingOptions {
+    pub threads: Option<Auto<u32>>,
+    // log2 of the number of tiles, VP9 only
+    pub tile_columns: Option<Auto<u32>>,
+    pub tile_rows: Option<u32>,
+    pub row_mt: Option<bool>,
+    pub frame_parallel_decoding: Option<bool>,
+}
+
+impl ThreadingOptions {
+    // Returns whether the key is a threading option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "threads" => self.threads = Auto::parse(value)?.or(self.threads),
+            "tile_columns" => self.tile_columns = Auto::parse(value)?.or(self.tile_columns),
+            "tile_rows" => self.tile_rows = value.get_uint32().or(self.tile_rows),
+            "row_mt" => self.row_mt = value.get_bool().or(self.row_mt),
+            "frame_parallel_decoding" => self.frame_parallel_decoding = value.get_bool().or(self.frame_parallel_decoding),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // Resolve the automatic values to thread count and log2 tile columns for
+    // the resolution
+    pub(super) fn resolve(&self, id: CodecID, width: u32, height: u32) -> (Option<u32>, Option<u32>) {
+        let row_mt = self.row_mt.unwrap_or(false);
+
+        let mut threads = self.threads.map(|threads| match threads {
+            Auto::Auto => auto_threads(width, height),
+            Auto::Value(threads) => threads,
+        });
+
+        if id == CodecID::VP8 {
+            return (threads, None);
+        }
+
+        let tile_columns = self.tile_columns.map(|tile_columns| match tile_columns {
+            Auto::Auto => threads.unwrap_or_else(|| auto_threads(width, height)).next_power_of_two().ilog2().min(max_tile_columns_log2(width)),
+            Auto::Value(tile_columns) => tile_columns,
+        });
+
+        // Without row multithreading VP9 runs one thread per tile column
+        if let (Some(Auto::Auto), Some(tile_columns), false) = (self.threads, tile_columns, row_mt) {
+            threads = threads.map(|threads| threads.min(1 << tile_columns));
+        }
+
+        (threads, tile_columns)
+    }
+}
+
+fn auto_threads(width: u32, height: u32) -> u32 {
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get() as u32);
+    let pixels = width as u64 * height as u64;
+
+    let threads = if pixels >= 3840 * 2160 {
+        16
+    } else if pixels >= 1920 * 1080 {
+        8
+    } else if pixels >= 1280 * 720 {
+        4
+    } else if pixels >= 640 * 360 {
+        2
+    } else {
+        1
+    };
+
+    threads.min(cores)
+}
+
+fn max_tile_columns_log2(width: u32) -> u32 {
+    (width / MIN_TILE_WIDTH).max(1).ilog2().min(MAX_TILE_COLUMNS_LOG2)
+}
@@ -0,0 +1,75 @@
+use std::os::raw::c_int;
+
+use media_core::{error::Error, invalid_param_error, rational::Rational64, Result};
+
+use crate::vpx_sys::vpx_rational;
+
+// Fine enough for the timestamps of variable frame rate input to stay distinct
+const DEFAULT_TIME_BASE: (i64, i64) = (1, 90000);
+
+// The time base option, a time base derived from the frame rate would merge
+// the timestamps of variable frame rate input
+pub(super) fn encoder_time_base(time_base: Option<Rational64>) -> Result<vpx_rational> {
+    let time_base = time_base.unwrap_or_else(|| Rational64::new(DEFAULT_TIME_BASE.0, DEFAULT_TIME_BASE.1));
+
+    Ok(vpx_rational {
+        num: c_int::try_from(*time_base.numer()).map_err(|_| invalid_param_error!(time_base))?,
+        den: c_int::try_from(*time_base.denom()).map_err(|_| invalid_param_error!(time_base))?,
+    })
+}
+
+// Timestamps of the input frames in the encoder time base, frames without a
+// pts follow the previous one and frames without a duration last one frame
+// interval, so variable frame rate input only needs the pts
+#[derive(Debug)]
+pub(super) struct Timestamps {
+    time_base: Rational64,
+    frame_duration: i64,
+    next_pts: i64,
+    last_pts: Option<i64>,
+}
+
+impl Timestamps {
+    pub fn new(time_base: vpx_rational, frame_rate: Option<Rational64>) -> Self {
+        let time_base = Rational64::new(time_base.num as i64, time_base.den as i64);
+        let frame_duration =
+            frame_rate.filter(|frame_rate| *frame_rate.numer() > 0).map_or(1, |frame_rate| rescale(1, frame_rate.recip(), time_base).max(1));
+
+        Self {
+            time_base,
+            frame_duration,
+            next_pts: 0,
+            last_pts: None,
+        }
+    }
+
+    // Returns the pts and duration in the encoder time base
+    pub fn next(&mut self, pts: Option<i64>, duration: Option<i64>, time_base: Option<Rational64>) -> Result<(i64, i64)> {
+        let from = time_base.unwrap_or(self.time_base);
+        let pts = pts.map_or(self.next_pts, |pts| rescale(pts, from, self.time_base));
+        let duration =
+            duration.map(|duration| rescale(duration, from, self.time_base)).filter(|duration| *duration > 0).unwrap_or(self.frame_duration);
+
+        // The encoder output is matched to the input by pts
+        if self.last_pts.is_some_and(|last_pts| pts <= last_pts) {
+            return Err(Error::Invalid(format!("pts {} is not increasing", pts).into()));
+        }
+
+        self.last_pts = Some(pts);
+        self.next_pts = pts + duration;
+
+        Ok((pts, duration))
+    }
+}
+
+// Rounds to the nearest unit of the target time base
+fn rescale(value: i64, from: Rational64, to: Rational64) -> i64 {
+    if from == to {
+        return value;
+    }
+
+    let num = value as i128 * *from.numer() as i128 * *to.denom() as i128;
+    let den = *from.denom() as i128 * *to.numer() as i128;
+
+    (num * 2 + den).div_euclid(den * 2) as i64
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::VecDeque,
+    slice,
+    sync::{Arc, Mutex},
+};
+
+use media_core::{unsupported_error, Result};
+
+use super::{
+    FirstPassStats, FrameDecision, FrameInfo, FrameResult, GopDecision, KeyFrameDecision, RateControlConfig, RateControlType, RateController,
+};
+use crate::vpx_sys::{VpxTplBlockStats, VpxTplFrameStats, VpxTplGopStats};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TplBlockStats {
+    // Position in units of 8x8 blocks
+    pub row: i16,
+    pub col: i16,
+    pub intra_cost: i64,
+    pub inter_cost: i64,
+    // Motion vector in 1/8 pixels
+    pub mv_row: i16,
+    pub mv_col: i16,
+    // Rate and distortion of predicting from the source of the reference
+    pub srcrf_rate: i64,
+    pub srcrf_dist: i64,
+    pub inter_pred_err: i64,
+    pub intra_pred_err: i64,
+    // Coding index of the reference frame, -1 for intra blocks
+    pub ref_frame_index: i32,
+}
+
+impl From<&VpxTplBlockStats> for TplBlockStats {
+    fn from(stats: &VpxTplBlockStats) -> Self {
+        Self {
+            row: stats.row,
+            col: stats.col,
+            intra_cost: stats.intra_cost,
+            inter_cost: stats.inter_cost,
+            mv_row: stats.mv_r,
+            mv_col: stats.mv_c,
+            srcrf_rate: stats.srcrf_rate,
+            srcrf_dist: stats.srcrf_dist,
+            inter_pred_err: stats.inter_pred_err,
+            intra_pred_err: stats.intra_pred_err,
+            ref_frame_index: stats.ref_frame_index,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TplFrameStats {
+    pub width: u32,
+    pub height: u32,
+    pub blocks: Vec<TplBlockStats>,
+}
+
+// Frames of the GOP in coding order
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TplGopStats {
+    pub frames: Vec<TplFrameStats>,
+}
+
+unsafe fn from_raw_parts<'a, T>(data: *const T, len: i32) -> &'a [T] {
+    if data.is_null() || len <= 0 {
+        return &[];
+    }
+
+    slice::from_raw_parts(data, len as usize)
+}
+
+impl From<&VpxTplFrameStats> for TplFrameStats {
+    fn from(stats: &VpxTplFrameStats) -> Self {
+        let blocks = unsafe { from_raw_parts(stats.block_stats_list, stats.num_blocks) };
+
+        Self {
+            width: stats.frame_width as u32,
+            height: stats.frame_height as u32,
+            blocks: blocks.iter().map(TplBlockStats::from).collect(),
+        }
+    }
+}
+
+impl From<&VpxTplGopStats> for TplGopStats {
+    fn from(stats: &VpxTplGopStats) -> Self {
+        let frames = unsafe { from_raw_parts(stats.frame_stats_list, stats.size) };
+
+        Self {
+            frames: frames.iter().map(TplFrameStats::from).collect(),
+        }
+    }
+}
+
+pub(super) type TplStatsQueue = Arc<Mutex<VecDeque<TplGopStats>>>;
+
+// Queues the TPL stats and forwards every call to the controller of the
+// application, libvpx makes the decisions when there is none
+pub(super) struct TplStatsCollector {
+    pub queue: TplStatsQueue,
+    pub controller: Option<Box<dyn RateController>>,
+}
+
+impl RateController for TplStatsCollector {
+    fn rate_control_type(&self) -> RateControlType {
+        self.controller.as_ref().map_or(RateControlType::None, |controller| controller.rate_control_type())
+    }
+
+    fn configure(&mut self, config: &RateControlConfig) -> Result<()> {
+        self.controller.as_mut().map_or(Ok(()), |controller| controller.configure(config))
+    }
+
+    fn frame_decision(&mut self, gop_index: i32) -> Result<FrameDecision> {
+        self.controller.as_mut().ok_or_else(|| unsupported_error!("frame_decision"))?.frame_decision(gop_index)
+    }
+
+    fn frame_result(&mut self, result: &FrameResult) -> Result<()> {
+        self.controller.as_mut().map_or(Ok(()), |controller| controller.frame_result(result))
+    }
+
+    fn frame_rdmult(&mut self, info: &FrameInfo) -> Result<i32> {
+        self.controller.as_mut().ok_or_else(|| unsupported_error!("frame_rdmult"))?.frame_rdmult(info)
+    }
+
+    fn key_frame_decision(&mut self) -> Result<KeyFrameDecision> {
+        self.controller.as_mut().ok_or_else(|| unsupported_error!("key_frame_decision"))?.key_frame_decision()
+    }
+
+    fn gop_decision(&mut self) -> Result<GopDecision> {
+        self.controller.as_mut().ok_or_else(|| unsupported_error!("gop_decision"))?.gop_decision()
+    }
+
+    fn first_pass_stats(&mut self, stats: &[FirstPassStats]) -> Result<()> {
+        self.controller.as_mut().map_or(Ok(()), |controller| controller.first_pass_stats(stats))
+    }
+
+    fn tpl_gop_stats(&mut self, stats: TplGopStats) -> Result<()> {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.push_back(stats.clone());
+        }
+
+        self.controller.as_mut().map_or(Ok(()), |controller| controller.tpl_gop_stats(stats))
+    }
+}
@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use media_core::{error::Error, unsupported_error, variant::Variant, Result};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preset {
+    Screenshare,
+    CameraRealtime,
+    FilmVod,
+    Animation,
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "screenshare" => Ok(Preset::Screenshare),
+            "camera-realtime" => Ok(Preset::CameraRealtime),
+            "film-vod" => Ok(Preset::FilmVod),
+            "animation" => Ok(Preset::Animation),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentType {
+    Default,
+    Screen,
+    Film,
+}
+
+impl FromStr for ContentType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default" => Ok(ContentType::Default),
+            "screen" => Ok(ContentType::Screen),
+            "film" => Ok(ContentType::Film),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tuning {
+    Psnr,
+    Ssim,
+}
+
+impl FromStr for Tuning {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "psnr" => Ok(Tuning::Psnr),
+            "ssim" => Ok(Tuning::Ssim),
+            _ => Err(unsupported_error!(s)),
+        }
+    }
+}
+
+// Controls left as None keep the encoder defaults
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TuningOptions {
+    pub content: Option<ContentType>,
+    // VP8 only, 0 off, 1 on, 2 aggressive rate control for screen content
+    pub screen_content_mode: Option<u32>,
+    pub tuning: Option<Tuning>,
+    // 0 to 7
+    pub sharpness: Option<u32>,
+    // 0 to 6 for VP8, 0 or 1 for VP9
+    pub noise_sensitivity: Option<u32>,
+    pub static_threshold: Option<u32>,
+}
+
+impl Preset {
+    pub fn options(&self) -> TuningOptions {
+        match self {
+            Preset::Screenshare => TuningOptions {
+                content: Some(ContentType::Screen),
+                screen_content_mode: Some(1),
+                tuning: Some(Tuning::Psnr),
+                sharpness: Some(0),
+                noise_sensitivity: Some(0),
+                static_threshold: Some(1),
+            },
+            Preset::CameraRealtime => TuningOptions {
+                content: Some(ContentType::Default),
+                screen_content_mode: Some(0),
+                tuning: Some(Tuning::Psnr),
+                sharpness: Some(0),
+                noise_sensitivity: Some(1),
+                static_threshold: Some(1),
+            },
+            Preset::FilmVod => TuningOptions {
+                content: Some(ContentType::Film),
+                screen_content_mode: Some(0),
+                tuning: Some(Tuning::Ssim),
+                sharpness: Some(0),
+                noise_sensitivity: Some(0),
+                static_threshold: Some(0),
+            },
+            Preset::Animation => TuningOptions {
+                content: Some(ContentType::Default),
+                screen_content_mode: Some(0),
+                tuning: Some(Tuning::Ssim),
+                sharpness: Some(3),
+                noise_sensitivity: Some(0),
+                static_threshold: Some(0),
+            },
+        }
+    }
+}
+
+impl TuningOptions {
+    // Returns whether the key is a tuning option
+    pub(super) fn update(&mut self, key: &str, value: &Variant) -> Result<bool> {
+        match key {
+            "tune_content" => {
+                if let Some(content) = value.get_string() {
+                    self.content = Some(content.parse()?);
+                }
+            }
+            "screen_content_mode" => self.screen_content_mode = value.get_uint32().or(self.screen_content_mode),
+            "tuning" => {
+                if let Some(tuning) = value.get_string() {
+                    self.tuning = Some(tuning.parse()?);
+                }
+            }
+            "sharpness" => self.sharpness = value.get_uint32().or(self.sharpness),
+            "noise_sensitivity" => self.noise_sensitivity = value.get_uint32().or(self.noise_sensitivity),
+            "static_threshold" => self.static_threshold = value.get_uint32().or(self.static_threshold),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // Controls set in other take precedence
+    pub fn merge(&mut self, other: &TuningOptions) {
+        self.content = other.content.or(self.content);
+        self.screen_content_mode = other.screen_content_mode.or(self.screen_content_mode);
+        self.tuning = other.tuning.or(self.tuning);
+        self.sharpness = other.sharpness.or(self.sharpness);
+        self.noise_sensitivity = other.noise_sensitivity.or(self.noise_sensitivity);
+        self.static_threshold = other.static_threshold.or(self.static_threshold);
+    }
+}
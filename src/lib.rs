@@ -1,4 +1,5 @@
 pub mod decoder;
+pub mod encoder;
 
 use std::{borrow::Cow, ffi::CStr};
 
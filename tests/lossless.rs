@@ -0,0 +1,168 @@
+use std::num::NonZeroU32;
+
+use media_codec::{
+    codec::{CodecID, VideoParameters},
+    decoder::{Decoder, DecoderParameters, VideoDecoder, VideoDecoderParameters},
+    encoder::{Encoder, EncoderParameters, VideoEncoder, VideoEncoderParameters},
+};
+use media_codec_vpx::{decoder::VpxDecoder, encoder::VpxEncoder};
+use media_core::{
+    error::Error,
+    frame::SharedFrame,
+    variant::Variant,
+    video::{PixelFormat, VideoFrame},
+};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 72;
+const FRAMES: i64 = 4;
+
+// Plane dimensions in samples of the 4:2:0 formats under test
+fn plane_size(plane: usize) -> (usize, usize) {
+    if plane == 0 {
+        (WIDTH as usize, HEIGHT as usize)
+    } else {
+        (WIDTH.div_ceil(2) as usize, HEIGHT.div_ceil(2) as usize)
+    }
+}
+
+fn new_frame(format: PixelFormat, bit_depth: u32, index: i64) -> VideoFrame<'static> {
+    let mut frame = VideoFrame::new(format, WIDTH, HEIGHT).unwrap();
+    let bytes_per_sample = bit_depth.div_ceil(8) as usize;
+    let mut seed = 0x9e37_79b9u32.wrapping_mul(index as u32 + 1);
+
+    {
+        let mut guard = frame.map_mut().unwrap();
+        let mut planes = guard.planes_mut().unwrap();
+
+        for plane in 0..3 {
+            let (width, height) = plane_size(plane);
+            let stride = planes.plane_stride(plane).unwrap();
+            let data = planes.plane_data_mut(plane).unwrap();
+
+            for y in 0..height {
+                for x in 0..width {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let sample = (seed >> 16) & ((1 << bit_depth) - 1);
+                    let offset = y * stride + x * bytes_per_sample;
+
+                    if bytes_per_sample == 2 {
+                        data[offset..offset + 2].copy_from_slice(&(sample as u16).to_le_bytes());
+                    } else {
+                        data[offset] = sample as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    frame.pts = Some(index);
+    frame
+}
+
+fn assert_frames_equal(expected: &VideoFrame, decoded: &VideoFrame, bit_depth: u32) {
+    let bytes_per_sample = bit_depth.div_ceil(8) as usize;
+    let expected_guard = expected.map().unwrap();
+    let decoded_guard = decoded.map().unwrap();
+    let expected_planes = expected_guard.planes().unwrap();
+    let decoded_planes = decoded_guard.planes().unwrap();
+
+    for plane in 0..3 {
+        let (width, height) = plane_size(plane);
+        let row_bytes = width * bytes_per_sample;
+        let expected_stride = expected_planes.plane_stride(plane).unwrap();
+        let decoded_stride = decoded_planes.plane_stride(plane).unwrap();
+        let expected_data = expected_planes.plane_data(plane).unwrap();
+        let decoded_data = decoded_planes.plane_data(plane).unwrap();
+
+        for y in 0..height {
+            assert_eq!(
+                &expected_data[y * expected_stride..y * expected_stride + row_bytes],
+                &decoded_data[y * decoded_stride..y * decoded_stride + row_bytes],
+                "plane {} row {} differs",
+                plane,
+                y
+            );
+        }
+    }
+}
+
+fn lossless_round_trip(format: PixelFormat, bit_depth: u32) {
+    let video = VideoParameters {
+        format: Some(format),
+        width: NonZeroU32::new(WIDTH),
+        height: NonZeroU32::new(HEIGHT),
+        ..Default::default()
+    };
+    let encoder_params = VideoEncoderParameters {
+        video: video.clone(),
+        encoder: EncoderParameters::default(),
+    };
+    let decoder_params = VideoDecoderParameters {
+        video: video.clone(),
+        decoder: DecoderParameters::default(),
+    };
+    let encoder_config = VideoEncoder {
+        video: video.clone(),
+        encoder: EncoderParameters::default(),
+    };
+    let decoder_config = VideoDecoder {
+        video,
+        decoder: DecoderParameters::default(),
+    };
+
+    let mut options = Variant::new_dict();
+    options.dict_set("lossless", true.into());
+
+    let mut encoder = VpxEncoder::new(CodecID::VP9, &encoder_params, Some(&options)).unwrap();
+    let mut decoder = VpxDecoder::new(CodecID::VP9, &decoder_params, None).unwrap();
+
+    let frames: Vec<_> = (0..FRAMES).map(|index| new_frame(format, bit_depth, index)).collect();
+    let mut packets = Vec::new();
+
+    for frame in &frames {
+        encoder.send_frame(&encoder_config, None, SharedFrame::<VideoFrame<'static>>::new(frame.clone())).unwrap();
+    }
+    encoder.flush(&encoder_config).unwrap();
+
+    loop {
+        match encoder.receive_packet(&encoder_config, None) {
+            Ok(packet) => packets.push(packet),
+            Err(Error::Again(_)) => break,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    let mut decoded = Vec::new();
+
+    for packet in &packets {
+        decoder.send_packet(&decoder_config, None, packet).unwrap();
+
+        loop {
+            match decoder.receive_frame(&decoder_config, None) {
+                Ok(frame) => decoded.push(frame),
+                Err(Error::Again(_)) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    assert_eq!(decoded.len(), frames.len());
+
+    for (expected, frame) in frames.iter().zip(&decoded) {
+        let frame = frame.read();
+
+        assert_eq!(frame.descriptor().format, format);
+        assert_frames_equal(expected, frame, bit_depth);
+    }
+}
+
+#[test]
+fn vp9_lossless_8bit() {
+    lossless_round_trip(PixelFormat::I420, 8);
+}
+
+#[test]
+fn vp9_lossless_10bit() {
+    lossless_round_trip(PixelFormat::I010, 10);
+}